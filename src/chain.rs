@@ -0,0 +1,200 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::Utc;
+use fehler::*;
+
+use crate::dao::url_dao::UrlDao;
+use crate::metadata::PublicResolver;
+use crate::utils::normalize_url;
+
+/// Our own micro urls followed before a destination counts as looping.
+const MAX_OWN_HOPS: usize = 10;
+
+/// Follows the redirects of known shorteners a hop at a time, so that only hops to other known
+/// shorteners are followed further.
+#[derive(Debug, Clone)]
+pub struct ShortenerFollower {
+    /// lower case hosts
+    pub hosts: Vec<String>,
+    /// 0 leaves other shorteners' urls alone
+    pub max_hops: usize,
+    pub timeout: Duration,
+    /// for local development and tests only
+    pub allow_private: bool,
+}
+
+impl ShortenerFollower {
+    pub fn is_shortener(&self, url: &str) -> bool {
+        http_types::Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_ascii_lowercase()))
+            .is_some_and(|host| {
+                let host = host.strip_prefix("www.").unwrap_or(&host);
+                self.hosts.iter().any(|h| h == host)
+            })
+    }
+
+    /// Where `url` redirects to, none when it does not. Blocks.
+    pub fn next_hop(&self, url: &str) -> Option<String> {
+        let mut agent = ureq::agent();
+        if !self.allow_private {
+            agent.set_resolver(PublicResolver);
+        }
+        let mut response = agent.head(url).timeout(self.timeout).redirects(0).call();
+        if matches!(response.status(), 405 | 501) && response.synthetic_error().is_none() {
+            response = agent.get(url).timeout(self.timeout).redirects(0).call();
+        }
+        if response.synthetic_error().is_some() || !response.redirect() {
+            return None;
+        }
+        let location = response.header("location")?;
+        let next = http_types::Url::parse(url).ok()?.join(location).ok()?;
+        match next.scheme() {
+            "http" | "https" => Some(next.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Why a destination can not be used.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainError {
+    /// one of our micro urls that does not exist, ie the one being created
+    Missing(String),
+    /// goes round in circles
+    Loop(String),
+}
+
+impl std::fmt::Display for ChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainError::Missing(url) => write!(f, "[{}] is not a micro url", url),
+            ChainError::Loop(url) => write!(f, "[{}] redirects back to itself", url),
+        }
+    }
+}
+
+/// Where `long_url` ends up, our own plain micro urls replaced by their destinations and, up
+/// to `max_hops`, other shorteners' urls by where they redirect. Reaching `changing`, the micro
+/// url whose destination this becomes, is a loop.
+#[throws(anyhow::Error)]
+pub async fn resolve_chain(
+    url_dao: &UrlDao,
+    follower: &ShortenerFollower,
+    long_url: &str,
+    changing: Option<&str>,
+) -> Result<String, ChainError> {
+    let mut url = long_url.to_owned();
+    let mut seen = HashSet::new();
+    let (mut own_hops, mut shortener_hops) = (0, 0);
+    let resolved = loop {
+        if !seen.insert(normalize_url(&url)) || own_hops > MAX_OWN_HOPS {
+            break Err(ChainError::Loop(long_url.to_owned()));
+        }
+        if let Some((id, domain)) = url_dao.own_id(&url) {
+            if changing == Some(id.as_str()) {
+                break Err(ChainError::Loop(long_url.to_owned()));
+            }
+            // on another host than its own the micro url is not found
+            let found = url_dao
+                .get_micro_url(&id)
+                .await?
+                .filter(|data| data.domain.as_deref().map(str::to_ascii_lowercase) == domain);
+            match found {
+                Some(data) if data.is_plain() => {
+                    url = data.long_url_at(Utc::now()).to_owned();
+                    own_hops += 1;
+                    continue;
+                }
+                // splits, schedules and passwords only work through the micro url
+                Some(_) => break Ok(url),
+                None => break Err(ChainError::Missing(url)),
+            }
+        }
+        if shortener_hops < follower.max_hops && follower.is_shortener(&url) {
+            let (follower, current) = (follower.clone(), url.to_owned());
+            let next = async_std::task::spawn_blocking(move || follower.next_hop(&current)).await;
+            if let Some(next) = next {
+                url = next;
+                shortener_hops += 1;
+                continue;
+            }
+        }
+        break Ok(url);
+    };
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers each request with the next of `responses`, a status and a location.
+    fn stub(responses: Vec<(u16, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/a", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for (stream, (status, location)) in listener.incoming().zip(responses) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {} Stub\r\nlocation: {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status, location
+                );
+            }
+        });
+        url
+    }
+
+    fn follower() -> ShortenerFollower {
+        ShortenerFollower {
+            hosts: vec!["127.0.0.1".to_owned(), "bit.ly".to_owned()],
+            max_hops: 3,
+            timeout: Duration::from_secs(5),
+            allow_private: true,
+        }
+    }
+
+    #[test]
+    fn shorteners() {
+        let follower = follower();
+        assert!(follower.is_shortener("https://bit.ly/abc"));
+        assert!(follower.is_shortener("https://WWW.bit.ly/abc"));
+        assert!(!follower.is_shortener("https://notbit.ly/abc"));
+        assert!(!follower.is_shortener("not a url"));
+    }
+
+    #[test]
+    fn next_hops() {
+        let follower = follower();
+        let url = stub(vec![(301, "https://example.com/sale")]);
+        assert_eq!(
+            follower.next_hop(&url).as_deref(),
+            Some("https://example.com/sale")
+        );
+
+        // relative locations, and a GET when HEAD is not allowed
+        let url = stub(vec![(405, ""), (302, "/b")]);
+        let next = follower.next_hop(&url).unwrap();
+        assert!(next.ends_with("/b"), "{}", next);
+
+        let url = stub(vec![(200, "")]);
+        assert_eq!(follower.next_hop(&url), None);
+
+        let url = stub(vec![(301, "javascript:alert(1)")]);
+        assert_eq!(follower.next_hop(&url), None);
+
+        // the stub is on a loopback address
+        let url = stub(vec![(301, "https://example.com/sale")]);
+        let public_only = ShortenerFollower {
+            allow_private: false,
+            ..follower
+        };
+        assert_eq!(public_only.next_hop(&url), None);
+    }
+}
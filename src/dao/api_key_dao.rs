@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Utc};
+use fehler::*;
+use rand::{thread_rng, Rng};
+use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
+
+/// Keys look like `utk_<id>_<secret>`, the prefix tells them apart from google id tokens.
+pub const API_KEY_PREFIX: &str = "utk_";
+
+/// What an api key may do, google logins may do everything.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+pub enum Scope {
+    #[serde(rename = "links:read")]
+    LinksRead,
+    #[serde(rename = "links:write")]
+    LinksWrite,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+    #[serde(rename = "workspaces:read")]
+    WorkspacesRead,
+    #[serde(rename = "workspaces:write")]
+    WorkspacesWrite,
+    /// the `/private` routes, only for keys of the configured admins
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// google email of whoever created it, the key acts as them
+    pub owner: String,
+    pub scopes: BTreeSet<Scope>,
+    pub created_at: DateTime<Utc>,
+    /// sha256 of the whole key, the key itself is only shown once
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_hash: String,
+    /// kept apart from the key so that using it does not rewrite it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// The id part of a key, none when it is not shaped like one.
+pub fn key_id(key: &str) -> Option<&str> {
+    let rest = key.strip_prefix(API_KEY_PREFIX)?;
+    let mut parts = rest.splitn(2, '_');
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(secret)) if !id.is_empty() && !secret.is_empty() => Some(id),
+        _ => None,
+    }
+}
+
+impl ApiKey {
+    /// A new api key and the key to hand out, which is not kept.
+    pub fn generate(name: &str, owner: &str, scopes: BTreeSet<Scope>) -> (ApiKey, String) {
+        let id = hex::encode(thread_rng().gen::<[u8; 6]>());
+        let key = format!(
+            "{}{}_{}",
+            API_KEY_PREFIX,
+            id,
+            hex::encode(thread_rng().gen::<[u8; 24]>())
+        );
+        let api_key = ApiKey {
+            id,
+            name: name.to_owned(),
+            owner: owner.to_owned(),
+            scopes,
+            created_at: Utc::now(),
+            key_hash: hash_key(&key),
+            last_used_at: None,
+        };
+        (api_key, key)
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        let hash = hash_key(key);
+        hash.len() == self.key_hash.len()
+            && hash
+                .bytes()
+                .zip(self.key_hash.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// Without the hash, for listing.
+    pub fn redacted(self) -> ApiKey {
+        ApiKey {
+            key_hash: String::new(),
+            ..self
+        }
+    }
+}
+
+fn api_key_key(id: &str) -> String {
+    format!("api_key:{}", id)
+}
+
+fn owner_api_keys_key(owner: &str) -> String {
+    format!("owner_api_keys:{}", owner)
+}
+
+pub const API_KEY_LAST_USED_KEY: &str = "api_key_last_used";
+
+#[derive(Clone)]
+pub struct ApiKeyDao {
+    redis_client: redis::Client,
+}
+
+impl ApiKeyDao {
+    #[throws(anyhow::Error)]
+    pub fn new(redis_urls_client_conn: &str) -> ApiKeyDao {
+        ApiKeyDao {
+            redis_client: redis::Client::open(redis_urls_client_conn)?,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn create_api_key(&self, api_key: &ApiKey) {
+        info!("create api key [{}] for [{}]", api_key.id, api_key.owner);
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.set::<_, _, ()>(api_key_key(&api_key.id), serde_json::to_string(api_key)?)
+            .await?;
+        con.sadd::<_, _, ()>(owner_api_keys_key(&api_key.owner), &api_key.id)
+            .await?;
+    }
+
+    #[throws(anyhow::Error)]
+    async fn get_api_key(&self, id: &str) -> Option<ApiKey> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let stored: Option<String> = con.get(api_key_key(id)).await?;
+        match stored {
+            Some(s) => {
+                let mut api_key: ApiKey = serde_json::from_str(&s)?;
+                let last_used: Option<String> = con.hget(API_KEY_LAST_USED_KEY, id).await?;
+                api_key.last_used_at = last_used.and_then(|l| l.parse().ok());
+                Some(api_key)
+            }
+            None => None,
+        }
+    }
+
+    /// The owner's keys, oldest first and without their hashes.
+    #[throws(anyhow::Error)]
+    pub async fn owned_api_keys(&self, owner: &str) -> Vec<ApiKey> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let ids: Vec<String> = con.smembers(owner_api_keys_key(owner)).await?;
+        let mut api_keys = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(k) = self.get_api_key(&id).await? {
+                api_keys.push(k.redacted());
+            }
+        }
+        api_keys.sort_by_key(|k| k.created_at);
+        api_keys
+    }
+
+    /// Deletes the key, false when `owner` has no key with this id.
+    #[throws(anyhow::Error)]
+    pub async fn revoke_api_key(&self, owner: &str, id: &str) -> bool {
+        match self.get_api_key(id).await? {
+            Some(k) if k.owner == owner => {
+                info!("revoke api key [{}] of [{}]", id, owner);
+                let mut con = self.redis_client.get_async_connection().await?;
+                con.del::<_, ()>(api_key_key(id)).await?;
+                con.srem::<_, _, ()>(owner_api_keys_key(owner), id).await?;
+                con.hdel::<_, _, ()>(API_KEY_LAST_USED_KEY, id).await?;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The api key for `key` when it exists, recording that it was used.
+    #[throws(anyhow::Error)]
+    pub async fn verify(&self, key: &str, now: DateTime<Utc>) -> Option<ApiKey> {
+        let api_key = match key_id(key) {
+            Some(id) => self.get_api_key(id).await?,
+            None => None,
+        };
+        match api_key {
+            Some(k) if k.matches(key) => {
+                let mut con = self.redis_client.get_async_connection().await?;
+                con.hset::<_, _, _, ()>(API_KEY_LAST_USED_KEY, &k.id, now.to_rfc3339())
+                    .await?;
+                Some(k)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let scopes = vec![Scope::LinksWrite].into_iter().collect();
+        let (api_key, key) = ApiKey::generate("ci", "a@example.com", scopes);
+        assert!(key.starts_with(API_KEY_PREFIX));
+        assert_eq!(key_id(&key), Some(api_key.id.as_str()));
+        assert!(api_key.matches(&key));
+        assert!(!api_key.matches(&format!("{}0", key)));
+        assert!(!api_key.key_hash.contains(&key));
+
+        let listed = serde_json::to_string(&api_key.redacted()).unwrap();
+        assert!(!listed.contains("key_hash"));
+        assert!(listed.contains("\"links:write\""));
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(key_id("utk_abc_def"), Some("abc"));
+        assert_eq!(key_id("utk_abc_"), None);
+        assert_eq!(key_id("utk_abc"), None);
+        assert_eq!(key_id("eyJ.eyJ.sig"), None);
+    }
+}
@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use fehler::*;
+use redis::AsyncCommands;
+
+/// The last time the health checker looked at a micro url's destination.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct LinkHealth {
+    pub long_url: String,
+    pub checked_at: DateTime<Utc>,
+    pub healthy: bool,
+    /// http status, none when there was no response at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// why there was no response, ie the host does not resolve
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// failed checks in a row, checks back off while this grows
+    pub failures: u32,
+    pub next_check_at: DateTime<Utc>,
+}
+
+fn health_key(id: &str) -> String {
+    format!("health:{}", id)
+}
+
+#[derive(Clone)]
+pub struct HealthDao {
+    redis_client: redis::Client,
+}
+
+impl HealthDao {
+    #[throws(anyhow::Error)]
+    pub fn new(redis_urls_client_conn: &str) -> HealthDao {
+        HealthDao {
+            redis_client: redis::Client::open(redis_urls_client_conn)?,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn get_health(&self, id: &str) -> Option<LinkHealth> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let stored: Option<String> = con.get(health_key(id)).await?;
+        match stored {
+            Some(s) => Some(serde_json::from_str(&s)?),
+            None => None,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn set_health(&self, id: &str, health: &LinkHealth) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.set::<_, _, ()>(health_key(id), serde_json::to_string(health)?)
+            .await?;
+    }
+}
@@ -0,0 +1,144 @@
+use fehler::*;
+use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
+
+/// How long a request can hold an idempotency key before a retry may take it over.
+const PENDING_SECONDS: usize = 60;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct IdempotencyRecord {
+    /// sha256 of the request body, the same key can not be reused for another request
+    request_hash: String,
+    /// the response body, none while the first request is still being handled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<String>,
+}
+
+/// What to do with a request that has an idempotency key.
+#[derive(Debug, PartialEq)]
+pub enum Idempotency {
+    /// first time this key is used, handle the request then call `finish`
+    New,
+    /// already handled, answer with this body
+    Done(String),
+    /// the first request with this key is still being handled
+    Pending,
+    /// the key was used for a different request
+    Mismatch,
+}
+
+pub fn request_hash(body: &str) -> String {
+    hex::encode(Sha256::digest(body.as_bytes()))
+}
+
+fn idempotency_key(scope: &str, key: &str) -> String {
+    // hashed so that callers can not make up huge redis keys
+    format!(
+        "idempotency:{}",
+        hex::encode(Sha256::digest(format!("{}\n{}", scope, key).as_bytes()))
+    )
+}
+
+#[derive(Clone)]
+pub struct IdempotencyDao {
+    redis_client: redis::Client,
+    ttl_seconds: usize,
+}
+
+impl IdempotencyDao {
+    #[throws(anyhow::Error)]
+    pub fn new(redis_urls_client_conn: &str, ttl_seconds: usize) -> IdempotencyDao {
+        IdempotencyDao {
+            redis_client: redis::Client::open(redis_urls_client_conn)?,
+            ttl_seconds,
+        }
+    }
+
+    /// Claims `key` for the caller in `scope`, unless it was used before.
+    #[throws(anyhow::Error)]
+    pub async fn begin(&self, scope: &str, key: &str, request_hash: &str) -> Idempotency {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let redis_key = idempotency_key(scope, key);
+        let pending = IdempotencyRecord {
+            request_hash: request_hash.to_owned(),
+            response: None,
+        };
+        let idempotency = loop {
+            let claimed: Option<String> = redis::cmd("SET")
+                .arg(&redis_key)
+                .arg(serde_json::to_string(&pending)?)
+                .arg("NX")
+                .arg("EX")
+                .arg(PENDING_SECONDS)
+                .query_async(&mut con)
+                .await?;
+            if claimed.is_some() {
+                break Idempotency::New;
+            }
+
+            let stored: Option<String> = con.get(&redis_key).await?;
+            // when it expired in between, try to claim it again
+            if let Some(stored) = stored {
+                let record: IdempotencyRecord = serde_json::from_str(&stored)?;
+                break match record.response {
+                    _ if record.request_hash != request_hash => Idempotency::Mismatch,
+                    Some(response) => Idempotency::Done(response),
+                    None => Idempotency::Pending,
+                };
+            }
+        };
+        idempotency
+    }
+
+    /// Gives up the claim of `begin` when the request failed, so that a retry is handled again.
+    #[throws(anyhow::Error)]
+    pub async fn release(&self, scope: &str, key: &str, request_hash: &str) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let redis_key = idempotency_key(scope, key);
+        let stored: Option<String> = con.get(&redis_key).await?;
+        if let Some(stored) = stored {
+            let record: IdempotencyRecord = serde_json::from_str(&stored)?;
+            if record.response.is_none() && record.request_hash == request_hash {
+                con.del::<_, ()>(&redis_key).await?;
+            }
+        }
+    }
+
+    /// Keeps the response for retries with the same key.
+    #[throws(anyhow::Error)]
+    pub async fn finish(&self, scope: &str, key: &str, request_hash: &str, response: &str) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let record = IdempotencyRecord {
+            request_hash: request_hash.to_owned(),
+            response: Some(response.to_owned()),
+        };
+        con.set_ex::<_, _, ()>(
+            idempotency_key(scope, key),
+            serde_json::to_string(&record)?,
+            self.ttl_seconds,
+        )
+        .await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert_eq!(
+            idempotency_key("email:a@example.com", "k1"),
+            idempotency_key("email:a@example.com", "k1")
+        );
+        // another caller can not see the response for the same key
+        assert_ne!(
+            idempotency_key("email:a@example.com", "k1"),
+            idempotency_key("email:b@example.com", "k1")
+        );
+        assert_ne!(
+            request_hash("{\"long_url\":\"a\"}"),
+            request_hash("{\"long_url\":\"b\"}")
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use fehler::*;
+use redis::AsyncCommands;
+
+use crate::metadata::PageMetadata;
+
+fn metadata_key(id: &str) -> String {
+    format!("metadata:{}", id)
+}
+
+/// What was scraped from micro urls' destinations, kept apart from the micro url data so the
+/// scraping never races with changes to it.
+#[derive(Clone)]
+pub struct MetadataDao {
+    redis_client: redis::Client,
+}
+
+impl MetadataDao {
+    #[throws(anyhow::Error)]
+    pub fn new(redis_urls_client_conn: &str) -> MetadataDao {
+        MetadataDao {
+            redis_client: redis::Client::open(redis_urls_client_conn)?,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn get_metadata(&self, id: &str) -> Option<PageMetadata> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let stored: Option<String> = con.get(metadata_key(id)).await?;
+        match stored {
+            Some(s) => Some(serde_json::from_str(&s)?),
+            None => None,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn set_metadata(&self, id: &str, metadata: &PageMetadata) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.set::<_, _, ()>(metadata_key(id), serde_json::to_string(metadata)?)
+            .await?;
+    }
+}
@@ -1 +1,6 @@
+pub mod api_key_dao;
+pub mod health_dao;
+pub mod idempotency_dao;
+pub mod metadata_dao;
 pub mod url_dao;
+pub mod workspace_dao;
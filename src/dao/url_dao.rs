@@ -1,21 +1,42 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use fehler::*;
 use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
 
-use crate::id_generator::IdGenerator;
-use crate::utils::trim_trailing_slash;
+use crate::id_filter::{FilteredIdGenerator, IdFilter};
+use crate::id_generator::{id_generator, Alphabet, DecodedId, IdGenerator, IdScheme};
+use crate::utils::{
+    encode_id, is_micro_url_id, normalize_id, normalize_url, trim_trailing_slash, typo_candidates,
+};
 use crate::AppConfig;
 
 #[derive(Clone)]
 pub struct UrlDao {
     redis_client: redis::Client,
-    id_generator: IdGenerator,
+    id_generator: Arc<dyn IdGenerator>,
     default_base_url: String,
+    /// base url by host, for the domains other than the default one
+    domain_base_urls: Arc<HashMap<String, String>>,
 }
 
 pub struct UrlDaoConfig {
     redis_urls_client_conn: String,
     default_base_url: String,
+    domain_base_urls: HashMap<String, String>,
+    id_scheme: IdScheme,
+    id_length: u8,
+    /// the scheme's own alphabet when none
+    id_alphabet: Option<Alphabet>,
+    /// obfuscates the counter of the counter scheme
+    id_counter_secret: Option<String>,
+    /// words ids must not spell, the default list when none
+    id_blocked_words_path: Option<PathBuf>,
+    id_exclude_ambiguous: bool,
 }
 
 pub trait IntoUrlDaoConfig {
@@ -26,43 +47,356 @@ pub trait IntoUrlDaoConfig {
 pub struct MicroUrlInfo {
     pub base_url: String,
     pub id: String,
+    /// percent encoded when the id is not ascii
     pub micro_url: String,
+    /// the micro url with the id as is, for showing to people
+    pub iri: String,
+}
+
+/// Everything stored for a micro url. Older entries were stored as the bare long url string, those
+/// are still read back as a `MicroUrlData` with only `long_url` set.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+pub struct MicroUrlData {
+    pub long_url: String,
+    /// weighted variants to split traffic between, when empty everyone goes to `long_url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<Destination>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_link: Option<AppLink>,
+    /// bcrypt hash, visitors have to enter the password before being redirected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// google email of whoever created it, anonymous when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// the micro url does not redirect anywhere before this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+    /// replaces `long_url` from each entry's `from` onwards, sorted by `from`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<ScheduledDestination>,
+    /// every `long_url` it has had, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<DestinationVersion>,
+    /// one of the configured extra hosts, the default host when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// shared with the members of this workspace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// lowercase, see `set_tags`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    /// shown to link preview bots instead of what was scraped from the destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub social: Option<SocialCard>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SocialCard {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// absolute url of the image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl SocialCard {
+    pub fn is_empty(&self) -> bool {
+        self == &SocialCard::default()
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ScheduledDestination {
+    pub from: DateTime<Utc>,
+    pub long_url: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Destination {
+    pub long_url: String,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct DestinationVersion {
+    pub version: u32,
+    pub long_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_by: Option<String>,
+    /// unknown for micro urls created before the history was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_at: Option<DateTime<Utc>>,
+}
+
+/// Deep links into a mobile app, the web fallback is the micro url's destination.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct AppLink {
+    /// app scheme uri, ie `myapp://product/1`
+    pub ios_url: Option<String>,
+    pub ios_store_url: Option<String>,
+    /// `intent://` url
+    pub android_url: Option<String>,
+    pub android_store_url: Option<String>,
+}
+
+/// Schemes a browser runs or reads from the device rather than handing to an app.
+const UNSAFE_APP_SCHEMES: &[&str] = &["javascript", "data", "vbscript", "file"];
+
+fn url_scheme(url: &str) -> Option<String> {
+    http_types::Url::parse(url)
+        .ok()
+        .map(|u| u.scheme().to_owned())
+}
+
+impl AppLink {
+    /// Every url of the app link, for the url policy.
+    pub fn urls(&self) -> Vec<&str> {
+        vec![
+            &self.ios_url,
+            &self.ios_store_url,
+            &self.android_url,
+            &self.android_store_url,
+        ]
+        .into_iter()
+        .filter_map(|u| u.as_deref())
+        .collect()
+    }
+
+    /// App urls may use their own scheme but none a browser would run, store urls are web pages.
+    pub fn is_valid(&self) -> bool {
+        let app_valid = |url: &Option<String>| {
+            url.as_deref().is_none_or(|u| {
+                url_scheme(u).is_some_and(|s| !UNSAFE_APP_SCHEMES.contains(&s.as_str()))
+            })
+        };
+        let store_valid = |url: &Option<String>| {
+            url.as_deref()
+                .is_none_or(|u| matches!(url_scheme(u).as_deref(), Some("http") | Some("https")))
+        };
+        app_valid(&self.ios_url)
+            && app_valid(&self.android_url)
+            && store_valid(&self.ios_store_url)
+            && store_valid(&self.android_store_url)
+    }
+}
+
+impl MicroUrlData {
+    pub fn new(long_url: &str) -> MicroUrlData {
+        MicroUrlData {
+            long_url: long_url.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.not_before.is_none_or(|nb| now >= nb)
+    }
+
+    /// The destination at `now`, following the schedule.
+    pub fn long_url_at(&self, now: DateTime<Utc>) -> &str {
+        self.scheduled_at(now)
+            .map_or(&self.long_url, |s| &s.long_url)
+    }
+
+    /// The schedule entry in force at `now`, none while `long_url` is.
+    pub fn scheduled_at(&self, now: DateTime<Utc>) -> Option<&ScheduledDestination> {
+        self.schedule.iter().rev().find(|s| s.from <= now)
+    }
+
+    /// The destination history, micro urls from before the history was kept get a single version.
+    pub fn versions(&self) -> Vec<DestinationVersion> {
+        if self.versions.is_empty() {
+            vec![DestinationVersion {
+                version: 1,
+                long_url: self.long_url.to_owned(),
+                changed_by: self.owner.to_owned(),
+                changed_at: None,
+            }]
+        } else {
+            self.versions.clone()
+        }
+    }
+
+    /// When the first version was made, unknown for micro urls from before the history was kept.
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        self.versions.first().and_then(|v| v.changed_at)
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.versions.last().map_or(1, |v| v.version)
+    }
+
+    pub fn change_long_url(
+        &mut self,
+        long_url: &str,
+        changed_by: Option<&str>,
+        now: DateTime<Utc>,
+    ) {
+        let mut versions = self.versions();
+        versions.push(DestinationVersion {
+            version: self.current_version() + 1,
+            long_url: long_url.to_owned(),
+            changed_by: changed_by.map(|s| s.to_owned()),
+            changed_at: Some(now),
+        });
+        self.versions = versions;
+        self.long_url = long_url.to_owned();
+    }
+
+    /// Points back at an older destination, as a new version so the history is kept. `long_url`
+    /// is where that version's destination leads now.
+    pub fn rollback(
+        &mut self,
+        version: u32,
+        long_url: &str,
+        changed_by: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if !self.versions().iter().any(|v| v.version == version) {
+            return false;
+        }
+        self.change_long_url(long_url, changed_by, now);
+        true
+    }
+
+    /// The version that was current at `at`, none before the micro url existed.
+    pub fn version_at(&self, at: DateTime<Utc>) -> Option<DestinationVersion> {
+        self.versions()
+            .into_iter()
+            .rev()
+            .find(|v| v.changed_at.is_none_or(|c| c <= at))
+    }
+
+    /// Whether it only has a destination, nothing that changes who gets sent where or when.
+    pub fn is_plain(&self) -> bool {
+        self.destinations.is_empty()
+            && self.app_link.is_none()
+            && self.password_hash.is_none()
+            && self.not_before.is_none()
+            && self.schedule.is_empty()
+    }
+
+    /// Trims and lowercases the tags so searching for one does not depend on how it was typed.
+    pub fn set_tags(&mut self, tags: &[String]) {
+        self.tags = tags
+            .iter()
+            .map(|t| t.trim().to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+    }
+
+    fn from_stored(stored: String) -> MicroUrlData {
+        if stored.starts_with('{') {
+            if let Ok(data) = serde_json::from_str(&stored) {
+                return data;
+            }
+            warn!("unable to parse stored micro url data, treating as a long url");
+        }
+        MicroUrlData::new(&stored)
+    }
 }
 
 impl IntoUrlDaoConfig for &AppConfig {
     fn into_url_dao_config(self) -> UrlDaoConfig {
+        let scheme = if self.cookie_secure { "https" } else { "http" };
         UrlDaoConfig {
             redis_urls_client_conn: self.redis_urls_client_conn.to_owned(),
-            default_base_url: format!(
-                "{}://{}",
-                if self.cookie_secure { "https" } else { "http" },
-                self.default_base_host
-            ),
+            default_base_url: format!("{}://{}", scheme, self.default_base_host),
+            domain_base_urls: self
+                .extra_base_hosts
+                .iter()
+                .map(|host| (host.to_ascii_lowercase(), format!("{}://{}", scheme, host)))
+                .collect(),
+            id_scheme: self.id_scheme,
+            id_length: self.id_length,
+            id_alphabet: self.id_alphabet.clone(),
+            id_counter_secret: self.id_counter_secret.as_ref().map(|s| s.0.to_owned()),
+            id_blocked_words_path: self.id_blocked_words_path.clone(),
+            id_exclude_ambiguous: self.id_exclude_ambiguous,
         }
     }
 }
 
+/// new ids tried before giving up on creating a micro url
+const MAX_ID_ATTEMPTS: u32 = 5;
+
+/// host and port, lower case, without the scheme's default port
+fn url_authority(url: &http_types::Url) -> Option<String> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host,
+    })
+}
+
+/// every micro url created since this set was added
+pub const LINKS_KEY: &str = "links";
+
+fn owner_links_key(owner: &str) -> String {
+    format!("owner_links:{}", owner)
+}
+
+/// the latest micro url of an owner for a normalized long url
+fn owner_url_key(owner: &str, long_url: &str) -> String {
+    let hash = Sha256::digest(normalize_url(long_url).as_bytes());
+    format!("owner_url:{}:{}", owner, hex::encode(hash))
+}
+
 impl UrlDao {
     #[throws(anyhow::Error)]
     pub fn new<T: IntoUrlDaoConfig>(config: T) -> UrlDao {
         let url_config: UrlDaoConfig = config.into_url_dao_config();
+        // the 64 characters of time ids always include every ambiguous one
+        anyhow::ensure!(
+            !(url_config.id_exclude_ambiguous && url_config.id_scheme == IdScheme::Time),
+            "time ids can not exclude ambiguous characters, use another id scheme"
+        );
         let redis_client = redis::Client::open(url_config.redis_urls_client_conn.as_str())?;
-        let id_generator = IdGenerator::new(8);
+        let id_generator = FilteredIdGenerator::new(
+            id_generator(
+                url_config.id_scheme,
+                url_config.id_length,
+                url_config.id_alphabet,
+                &url_config.redis_urls_client_conn,
+                url_config.id_counter_secret.as_deref(),
+            )?,
+            IdFilter::load(
+                url_config.id_blocked_words_path.as_deref(),
+                url_config.id_exclude_ambiguous,
+            )?,
+        );
         let default_base_url = trim_trailing_slash(&url_config.default_base_url);
+        let domain_base_urls = url_config
+            .domain_base_urls
+            .iter()
+            .map(|(host, base_url)| (host.to_owned(), trim_trailing_slash(base_url)))
+            .collect();
 
         UrlDao {
             redis_client,
-            id_generator,
+            id_generator: Arc::new(id_generator),
             default_base_url,
+            domain_base_urls: Arc::new(domain_base_urls),
         }
     }
 
+    /// Stores a new micro url under `alias`, or a generated id when none. None when the alias is
+    /// already taken.
     #[throws(anyhow::Error)]
-    pub async fn create_micro_url(&self, long_url: &str) -> MicroUrlInfo {
-        info!("create micro url of [{}]", long_url);
-
-        let id = self.id_generator.gen_id();
-        debug!("created id [{}] for long url [{}]", &id, long_url);
+    pub async fn create_micro_url(
+        &self,
+        data: &MicroUrlData,
+        alias: Option<&str>,
+    ) -> Option<MicroUrlInfo> {
+        info!("create micro url of [{}]", data.long_url);
 
         let mut con = self
             .redis_client
@@ -72,26 +406,497 @@ impl UrlDao {
                 "unable to get connection to redis, {:?}",
                 self.redis_client
             ))?;
-        con.set(&id, long_url).await?;
+        let json = serde_json::to_string(data)?;
+        let id = match alias {
+            Some(alias) => {
+                let created: bool = con.set_nx(alias, &json).await?;
+                if !created {
+                    return None;
+                }
+                alias.to_owned()
+            }
+            // short random ids can collide, never overwrite an existing micro url
+            None => {
+                let mut attempts = 0;
+                loop {
+                    let id = self.id_generator.gen_id().await?;
+                    let created: bool = con.set_nx(&id, &json).await?;
+                    if created {
+                        break id;
+                    }
+                    attempts += 1;
+                    if attempts >= MAX_ID_ATTEMPTS {
+                        throw!(anyhow::anyhow!(
+                            "no free id after {} attempts, ids may be too short",
+                            attempts
+                        ));
+                    }
+                    warn!("id [{}] is already taken, trying another", id);
+                }
+            }
+        };
+        debug!("created id [{}] for long url [{}]", &id, data.long_url);
+        con.sadd::<_, _, ()>(LINKS_KEY, &id).await?;
+        if let Some(ref owner) = data.owner {
+            con.sadd::<_, _, ()>(owner_links_key(owner), &id).await?;
+            con.set::<_, _, ()>(owner_url_key(owner, &data.long_url), &id)
+                .await?;
+        }
+
+        Some(self.micro_url_info(&id, data))
+    }
 
-        let micro_url = format!("{}/{}", self.default_base_url, &id);
+    #[throws(anyhow::Error)]
+    pub async fn all_links(&self) -> HashSet<String> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.smembers(LINKS_KEY).await?
+    }
+
+    /// The micro urls whose ids are a typo away from `id`, see `typo_candidates`.
+    #[throws(anyhow::Error)]
+    pub async fn near_micro_urls(&self, id: &str) -> Vec<(String, MicroUrlData)> {
+        self.get_micro_urls(&typo_candidates(id)).await?
+    }
+
+    /// The latest micro url `owner` made for the same normalized long url, when it still points
+    /// there.
+    #[throws(anyhow::Error)]
+    pub async fn find_owned_url(
+        &self,
+        owner: &str,
+        long_url: &str,
+    ) -> Option<(String, MicroUrlData)> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let id: Option<String> = con.get(owner_url_key(owner, long_url)).await?;
+        let found = match id {
+            Some(id) => self.get_micro_url(&id).await?.map(|data| (id, data)),
+            None => None,
+        };
+        found.filter(|(_, data)| {
+            data.owner.as_deref() == Some(owner)
+                && normalize_url(&data.long_url) == normalize_url(long_url)
+        })
+    }
+
+    /// Ids of the micro urls created by `owner`.
+    #[throws(anyhow::Error)]
+    pub async fn owned_links(&self, owner: &str) -> HashSet<String> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.smembers(owner_links_key(owner)).await?
+    }
+
+    /// The micro urls that exist out of `ids`, by id.
+    #[throws(anyhow::Error)]
+    pub async fn get_micro_urls(&self, ids: &[String]) -> Vec<(String, MicroUrlData)> {
+        let ids: Vec<String> = ids
+            .iter()
+            .filter(|id| is_micro_url_id(id))
+            .cloned()
+            .collect();
+        if ids.is_empty() {
+            return vec![];
+        }
+        let mut con = self.redis_client.get_async_connection().await?;
+        let stored: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&ids[..])
+            .query_async(&mut con)
+            .await?;
+        ids.into_iter()
+            .zip(stored)
+            .filter_map(|(id, s)| s.map(|s| (id, MicroUrlData::from_stored(s))))
+            .collect()
+    }
+
+    /// Replaces the data of a micro url that is still `old`, false when it changed meanwhile or
+    /// does not exist.
+    #[throws(anyhow::Error)]
+    pub async fn update_micro_url(
+        &self,
+        id: &str,
+        old: &MicroUrlData,
+        data: &MicroUrlData,
+    ) -> bool {
+        info!("update micro url [{}]", id);
+        if !is_micro_url_id(id) {
+            return false;
+        }
+        let mut con = self.redis_client.get_async_connection().await?;
+
+        // the owner's index for deduplicating follows the destination
+        let owner_url_keys = match data.owner {
+            Some(ref owner) if normalize_url(&old.long_url) != normalize_url(&data.long_url) => {
+                Some((
+                    owner_url_key(owner, &old.long_url),
+                    owner_url_key(owner, &data.long_url),
+                ))
+            }
+            _ => None,
+        };
+        let mut watch = redis::cmd("WATCH");
+        watch.arg(id);
+        if let Some((ref old_key, _)) = owner_url_keys {
+            watch.arg(old_key);
+        }
+        watch.query_async::<_, ()>(&mut con).await?;
+        let stored: Option<String> = con.get(id).await?;
+        let unchanged = match stored {
+            Some(s) => {
+                serde_json::to_value(MicroUrlData::from_stored(s))? == serde_json::to_value(old)?
+            }
+            None => false,
+        };
+        if !unchanged {
+            redis::cmd("UNWATCH").query_async::<_, ()>(&mut con).await?;
+            return false;
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("SET")
+            .arg(id)
+            .arg(serde_json::to_string(data)?)
+            .arg("XX");
+        if let Some((old_key, new_key)) = owner_url_keys {
+            let old_id: Option<String> = con.get(&old_key).await?;
+            if old_id.as_deref() == Some(id) {
+                pipe.del(old_key).ignore();
+            }
+            pipe.set(new_key, id).ignore();
+        }
+        // none when a watched key was written before the exec
+        let updated: Option<(Option<String>,)> = pipe.query_async(&mut con).await?;
+        matches!(updated, Some((Some(_),)))
+    }
+
+    pub fn micro_url_info(&self, id: &str, data: &MicroUrlData) -> MicroUrlInfo {
+        let base_url = self.base_url(data.domain.as_deref());
         MicroUrlInfo {
-            base_url: self.default_base_url.to_string(),
-            id,
-            micro_url,
+            base_url: base_url.to_string(),
+            id: id.to_owned(),
+            micro_url: format!("{}/{}", base_url, encode_id(id)),
+            iri: format!("{}/{}", base_url, id),
+        }
+    }
+
+    /// Whether `host` is one of the extra domains micro urls can be created on.
+    pub fn has_domain(&self, host: &str) -> bool {
+        self.domain_base_urls
+            .contains_key(&host.to_ascii_lowercase())
+    }
+
+    /// The id of one of our own micro urls when `url` is one, on any of our domains, and the
+    /// extra domain it is on, none for the default one.
+    pub fn own_id(&self, url: &str) -> Option<(String, Option<String>)> {
+        let url = http_types::Url::parse(url).ok()?;
+        let authority = url_authority(&url)?;
+        let is_base = |base: &str| {
+            http_types::Url::parse(base)
+                .ok()
+                .and_then(|base| url_authority(&base))
+                == Some(authority.to_owned())
+        };
+        let domain = match self.domain_base_urls.iter().find(|(_, base)| is_base(base)) {
+            Some((domain, _)) => Some(domain.to_owned()),
+            None if is_base(&self.default_base_url) => None,
+            None => return None,
+        };
+        let id = url.path().strip_prefix('/')?;
+        // only the redirect itself, not ie previews and qr codes
+        if id.is_empty() || id.contains('/') || id.ends_with('+') {
+            return None;
         }
+        Some((normalize_id(id), domain))
+    }
+
+    /// What the configured id scheme can read from `id`, ie when it was made.
+    pub fn decode_id(&self, id: &str) -> Option<DecodedId> {
+        self.id_generator.decode(id)
+    }
+
+    fn base_url(&self, domain: Option<&str>) -> &str {
+        domain
+            .and_then(|d| self.domain_base_urls.get(&d.to_ascii_lowercase()))
+            .unwrap_or(&self.default_base_url)
     }
 
     #[throws(anyhow::Error)]
-    pub async fn get_micro_url(&self, id: &str) -> Option<String> {
+    pub async fn get_micro_url(&self, id: &str) -> Option<MicroUrlData> {
         info!("get long url from micro id [{}]", id);
+        if !is_micro_url_id(id) {
+            return None;
+        }
         let mut con = self.redis_client.get_async_connection().await?;
 
-        let long_url = con.get(id).await?;
-        match long_url {
-            Some(ref u) => debug!("found id [{}] with long url [{}]", &id, u),
+        let stored: Option<String> = con.get(id).await?;
+        match stored {
+            Some(ref u) => debug!("found id [{}] with data [{}]", &id, u),
             None => debug!("unable to find id [{}]", &id),
         }
-        long_url
+        stored.map(MicroUrlData::from_stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    impl IntoUrlDaoConfig for UrlDaoConfig {
+        fn into_url_dao_config(self) -> UrlDaoConfig {
+            self
+        }
+    }
+
+    fn test_dao() -> UrlDao {
+        UrlDao::new(UrlDaoConfig {
+            redis_urls_client_conn: crate::fake_redis::start(),
+            default_base_url: "http://localhost:8080".to_owned(),
+            domain_base_urls: HashMap::new(),
+            id_scheme: IdScheme::Time,
+            id_length: 8,
+            id_alphabet: None,
+            id_counter_secret: None,
+            id_blocked_words_path: None,
+            id_exclude_ambiguous: false,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn time_ids_can_not_exclude_ambiguous() {
+        let config = |id_scheme| UrlDaoConfig {
+            redis_urls_client_conn: crate::fake_redis::start(),
+            default_base_url: "http://localhost:8080".to_owned(),
+            domain_base_urls: HashMap::new(),
+            id_scheme,
+            id_length: 8,
+            id_alphabet: None,
+            id_counter_secret: None,
+            id_blocked_words_path: None,
+            id_exclude_ambiguous: true,
+        };
+        assert!(UrlDao::new(config(IdScheme::Time)).is_err());
+        assert!(UrlDao::new(config(IdScheme::Random)).is_ok());
+    }
+
+    #[async_std::test]
+    async fn update_only_what_was_read() {
+        let dao = test_dao();
+        let data = MicroUrlData::new("http://example.com/1");
+        let id = dao.create_micro_url(&data, None).await.unwrap().unwrap().id;
+
+        let mut first = data.clone();
+        first.title = Some("first".to_owned());
+        let mut second = data.clone();
+        second.title = Some("second".to_owned());
+        assert!(dao.update_micro_url(&id, &data, &first).await.unwrap());
+        // read before the first update went in
+        assert!(!dao.update_micro_url(&id, &data, &second).await.unwrap());
+        let stored = dao.get_micro_url(&id).await.unwrap().unwrap();
+        assert_eq!(stored.title.as_deref(), Some("first"));
+
+        assert!(!dao
+            .update_micro_url("missing", &data, &first)
+            .await
+            .unwrap());
+    }
+
+    #[async_std::test]
+    async fn owned_urls_follow_changes() {
+        let dao = test_dao();
+        let owner = "a@example.com";
+        let mut data = MicroUrlData::new("http://example.com/1");
+        data.owner = Some(owner.to_owned());
+        let id = dao.create_micro_url(&data, None).await.unwrap().unwrap().id;
+        let found = |long_url: &'static str| {
+            let dao = &dao;
+            async move {
+                dao.find_owned_url(owner, long_url)
+                    .await
+                    .unwrap()
+                    .map(|(id, _)| id)
+            }
+        };
+        assert_eq!(found("http://example.com/1").await, Some(id.to_owned()));
+
+        let mut changed = data.clone();
+        changed.change_long_url("http://example.com/2", Some(owner), Utc::now());
+        assert!(dao.update_micro_url(&id, &data, &changed).await.unwrap());
+        assert_eq!(found("http://example.com/1").await, None);
+        assert_eq!(found("http://example.com/2").await, Some(id));
+    }
+
+    #[test]
+    fn legacy() {
+        let data = MicroUrlData::from_stored("http://example.com".to_owned());
+        assert_eq!(data.long_url, "http://example.com");
+        let data = MicroUrlData::from_stored(r#"{"long_url":"http://example.com/a"}"#.to_owned());
+        assert_eq!(data.long_url, "http://example.com/a");
+    }
+
+    #[test]
+    fn schedule() {
+        let data = MicroUrlData {
+            not_before: Some(at("2020-06-01T00:00:00Z")),
+            schedule: vec![
+                ScheduledDestination {
+                    from: at("2020-06-05T09:00:00Z"),
+                    long_url: "http://example.com/product".to_owned(),
+                },
+                ScheduledDestination {
+                    from: at("2020-07-01T00:00:00Z"),
+                    long_url: "http://example.com/sale".to_owned(),
+                },
+            ],
+            ..MicroUrlData::new("http://example.com/pre-launch")
+        };
+        assert!(!data.is_active(at("2020-05-31T23:59:59Z")));
+        assert!(data.is_active(at("2020-06-01T00:00:00Z")));
+        assert_eq!(
+            data.long_url_at(at("2020-06-05T08:59:59Z")),
+            "http://example.com/pre-launch"
+        );
+        assert_eq!(
+            data.long_url_at(at("2020-06-05T09:00:00Z")),
+            "http://example.com/product"
+        );
+        assert_eq!(
+            data.long_url_at(at("2020-08-01T00:00:00Z")),
+            "http://example.com/sale"
+        );
+    }
+
+    #[test]
+    fn tags() {
+        let mut data = MicroUrlData::new("https://example.com");
+        data.set_tags(&[
+            " Launch ".to_owned(),
+            "".to_owned(),
+            "launch".to_owned(),
+            "q3".to_owned(),
+        ]);
+        assert_eq!(
+            data.tags.iter().map(|t| t.as_str()).collect::<Vec<_>>(),
+            vec!["launch", "q3"]
+        );
+    }
+
+    #[test]
+    fn history() {
+        let mut data = MicroUrlData::new("http://example.com/1");
+        assert_eq!(data.current_version(), 1);
+        assert_eq!(data.versions().len(), 1);
+
+        data.change_long_url(
+            "http://example.com/2",
+            Some("a@example.com"),
+            at("2020-06-01T00:00:00Z"),
+        );
+        data.change_long_url("http://example.com/3", None, at("2020-07-01T00:00:00Z"));
+        assert_eq!(data.current_version(), 3);
+        assert_eq!(data.long_url, "http://example.com/3");
+
+        assert!(data.rollback(
+            2,
+            "http://example.com/2",
+            Some("b@example.com"),
+            at("2020-08-01T00:00:00Z")
+        ));
+        assert!(!data.rollback(9, "http://example.com/9", None, at("2020-08-01T00:00:00Z")));
+        assert_eq!(data.current_version(), 4);
+        assert_eq!(data.long_url, "http://example.com/2");
+        assert_eq!(
+            data.versions()[3].changed_by.as_deref(),
+            Some("b@example.com")
+        );
+
+        let long_url_at = |t| data.version_at(at(t)).map(|v| v.long_url);
+        assert_eq!(
+            long_url_at("2020-01-01T00:00:00Z").unwrap(),
+            "http://example.com/1"
+        );
+        assert_eq!(
+            long_url_at("2020-06-15T00:00:00Z").unwrap(),
+            "http://example.com/2"
+        );
+        assert_eq!(
+            long_url_at("2020-07-01T00:00:00Z").unwrap(),
+            "http://example.com/3"
+        );
+        assert_eq!(
+            long_url_at("2021-01-01T00:00:00Z").unwrap(),
+            "http://example.com/2"
+        );
+
+        let mut created = MicroUrlData::new("http://example.com/1");
+        created.versions = created.versions();
+        created.versions[0].changed_at = Some(at("2020-06-01T00:00:00Z"));
+        assert_eq!(created.version_at(at("2020-05-01T00:00:00Z")), None);
+    }
+
+    #[test]
+    fn app_links() {
+        let app_link = |ios_url: &str, ios_store_url: &str| AppLink {
+            ios_url: Some(ios_url.to_owned()),
+            ios_store_url: Some(ios_store_url.to_owned()),
+            android_url: None,
+            android_store_url: None,
+        };
+        let store = "https://apps.apple.com/app/id1";
+        assert!(app_link("myapp://product/1", store).is_valid());
+        assert!(app_link("intent://product/1#Intent;scheme=myapp;end", store).is_valid());
+        assert!(!app_link("javascript:alert(document.cookie)", store).is_valid());
+        assert!(!app_link(" JavaScript:alert(1)", store).is_valid());
+        assert!(!app_link("data:text/html,<script>alert(1)</script>", store).is_valid());
+        assert!(!app_link("vbscript:msgbox(1)", store).is_valid());
+        assert!(!app_link("file:///etc/passwd", store).is_valid());
+        assert!(!app_link("not a url", store).is_valid());
+        assert!(!app_link("myapp://product/1", "javascript:alert(1)").is_valid());
+        assert!(!app_link("myapp://product/1", "myapp://store").is_valid());
+        assert_eq!(
+            app_link("myapp://product/1", store).urls(),
+            vec!["myapp://product/1", store]
+        );
+    }
+
+    struct OwnDomains;
+
+    impl IntoUrlDaoConfig for OwnDomains {
+        fn into_url_dao_config(self) -> UrlDaoConfig {
+            UrlDaoConfig {
+                redis_urls_client_conn: "redis://127.0.0.1/".to_owned(),
+                default_base_url: "https://utrakr.app/".to_owned(),
+                domain_base_urls: vec![("go.example.com:8080", "http://go.example.com:8080")]
+                    .into_iter()
+                    .map(|(h, b)| (h.to_owned(), b.to_owned()))
+                    .collect(),
+                id_scheme: IdScheme::Time,
+                id_length: 8,
+                id_alphabet: None,
+                id_counter_secret: None,
+                id_blocked_words_path: None,
+                id_exclude_ambiguous: false,
+            }
+        }
+    }
+
+    #[test]
+    fn own_ids() {
+        let url_dao = UrlDao::new(OwnDomains).unwrap();
+        let own = |url| url_dao.own_id(url);
+        let default = |id: &str| Some((id.to_owned(), None));
+        assert_eq!(own("https://utrakr.app/abc"), default("abc"));
+        assert_eq!(own("http://UTRAKR.app:80/abc?x=1"), default("abc"));
+        assert_eq!(
+            own("https://go.example.com:8080/caf%C3%A9"),
+            Some(("café".to_owned(), Some("go.example.com:8080".to_owned())))
+        );
+        assert_eq!(own("https://go.example.com/abc"), None);
+        assert_eq!(own("https://utrakr.app/abc+"), None);
+        assert_eq!(own("https://utrakr.app/abc/qr"), None);
+        assert_eq!(own("https://utrakr.app/"), None);
+        assert_eq!(own("https://example.com/abc"), None);
     }
 }
@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use fehler::*;
+use rand::{thread_rng, Rng};
+use redis::AsyncCommands;
+
+/// Ordered so that a higher role can do everything a lower one can.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Deserialize, serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Workspace {
+    pub id: String,
+    pub name: String,
+    /// role by google email
+    pub members: BTreeMap<String, Role>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Workspace {
+    pub fn role_of(&self, email: &str) -> Option<Role> {
+        self.members.get(email).copied()
+    }
+
+    /// Sets or removes (`None`) a member's role, refusing to leave the workspace without an owner.
+    pub fn set_role(&mut self, email: &str, role: Option<Role>) -> bool {
+        let previous = match role {
+            Some(r) => self.members.insert(email.to_owned(), r),
+            None => self.members.remove(email),
+        };
+        if !self.members.values().any(|r| *r == Role::Owner) {
+            match previous {
+                Some(p) => self.members.insert(email.to_owned(), p),
+                None => self.members.remove(email),
+            };
+            return false;
+        }
+        true
+    }
+}
+
+fn workspace_key(id: &str) -> String {
+    format!("workspace:{}", id)
+}
+
+fn member_workspaces_key(email: &str) -> String {
+    format!("member_workspaces:{}", email)
+}
+
+fn workspace_links_key(id: &str) -> String {
+    format!("workspace_links:{}", id)
+}
+
+#[derive(Clone)]
+pub struct WorkspaceDao {
+    redis_client: redis::Client,
+}
+
+impl WorkspaceDao {
+    #[throws(anyhow::Error)]
+    pub fn new(redis_urls_client_conn: &str) -> WorkspaceDao {
+        WorkspaceDao {
+            redis_client: redis::Client::open(redis_urls_client_conn)?,
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn create_workspace(&self, name: &str, owner: &str) -> Workspace {
+        let mut members = BTreeMap::new();
+        members.insert(owner.to_owned(), Role::Owner);
+        let workspace = Workspace {
+            id: hex::encode(thread_rng().gen::<[u8; 8]>()),
+            name: name.to_owned(),
+            members,
+            created_at: Utc::now(),
+        };
+        info!("create workspace [{}] for [{}]", workspace.id, owner);
+
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.set::<_, _, ()>(
+            workspace_key(&workspace.id),
+            serde_json::to_string(&workspace)?,
+        )
+        .await?;
+        con.sadd::<_, _, ()>(member_workspaces_key(owner), &workspace.id)
+            .await?;
+        workspace
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn get_workspace(&self, id: &str) -> Option<Workspace> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let stored: Option<String> = con.get(workspace_key(id)).await?;
+        match stored {
+            Some(s) => Some(serde_json::from_str(&s)?),
+            None => None,
+        }
+    }
+
+    /// Saves the workspace, keeping the per member index in step with `previous`.
+    #[throws(anyhow::Error)]
+    pub async fn update_workspace(&self, previous: &Workspace, workspace: &Workspace) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.set::<_, _, ()>(
+            workspace_key(&workspace.id),
+            serde_json::to_string(workspace)?,
+        )
+        .await?;
+        for email in previous.members.keys() {
+            if !workspace.members.contains_key(email) {
+                con.srem::<_, _, ()>(member_workspaces_key(email), &workspace.id)
+                    .await?;
+            }
+        }
+        for email in workspace.members.keys() {
+            con.sadd::<_, _, ()>(member_workspaces_key(email), &workspace.id)
+                .await?;
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn member_workspaces(&self, email: &str) -> Vec<Workspace> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let ids: Vec<String> = con.smembers(member_workspaces_key(email)).await?;
+        let mut workspaces = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(w) = self.get_workspace(&id).await? {
+                workspaces.push(w);
+            }
+        }
+        workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+        workspaces
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn add_link(&self, workspace: &str, id: &str) {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.sadd::<_, _, ()>(workspace_links_key(workspace), id)
+            .await?;
+    }
+
+    #[throws(anyhow::Error)]
+    pub async fn links(&self, workspace: &str) -> HashSet<String> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        con.smembers(workspace_links_key(workspace)).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles() {
+        assert!(Role::Owner > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+
+        let mut members = BTreeMap::new();
+        members.insert("a@example.com".to_owned(), Role::Owner);
+        let mut workspace = Workspace {
+            id: "w".to_owned(),
+            name: "w".to_owned(),
+            members,
+            created_at: Utc::now(),
+        };
+        assert!(workspace.set_role("b@example.com", Some(Role::Editor)));
+        assert_eq!(workspace.role_of("b@example.com"), Some(Role::Editor));
+        assert_eq!(workspace.role_of("c@example.com"), None);
+
+        // the last owner can not leave or be demoted
+        assert!(!workspace.set_role("a@example.com", None));
+        assert!(!workspace.set_role("a@example.com", Some(Role::Viewer)));
+        assert_eq!(workspace.role_of("a@example.com"), Some(Role::Owner));
+
+        assert!(workspace.set_role("b@example.com", Some(Role::Owner)));
+        assert!(workspace.set_role("a@example.com", None));
+        assert_eq!(workspace.role_of("a@example.com"), None);
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+
+use crate::dao::url_dao::MicroUrlData;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Filters over the links the caller can see, every given filter has to match.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkSearchRequest {
+    tag: Option<String>,
+    /// case insensitive substring of the long url or the title
+    q: Option<String>,
+    /// created at or after
+    from_date: Option<DateTime<Utc>>,
+    /// created before
+    to_date: Option<DateTime<Utc>>,
+    /// only the links of this workspace
+    workspace: Option<String>,
+    limit: Option<usize>,
+}
+
+impl LinkSearchRequest {
+    pub fn workspace(&self) -> Option<&str> {
+        self.workspace.as_deref()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+
+    /// Links from before the history was kept have no creation date, a date range never matches them.
+    pub fn matches(&self, data: &MicroUrlData) -> bool {
+        if let Some(ref tag) = self.tag {
+            if !data.tags.contains(&tag.trim().to_lowercase()) {
+                return false;
+            }
+        }
+        if let Some(ref q) = self.q {
+            let q = q.to_lowercase();
+            let in_title = data
+                .title
+                .as_ref()
+                .is_some_and(|t| t.to_lowercase().contains(&q));
+            if !in_title && !data.long_url.to_lowercase().contains(&q) {
+                return false;
+            }
+        }
+        if self.from_date.is_some() || self.to_date.is_some() {
+            let created_at = match data.created_at() {
+                Some(c) => c,
+                None => return false,
+            };
+            if self.from_date.is_some_and(|from| created_at < from)
+                || self.to_date.is_some_and(|to| created_at >= to)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dao::url_dao::DestinationVersion;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn link() -> MicroUrlData {
+        let mut data = MicroUrlData::new("https://example.com/Spring-Sale");
+        data.title = Some("Newsletter June".to_owned());
+        data.set_tags(&["Email".to_owned()]);
+        data.versions = vec![DestinationVersion {
+            version: 1,
+            long_url: data.long_url.to_owned(),
+            changed_by: None,
+            changed_at: Some(at("2020-06-01T00:00:00Z")),
+        }];
+        data
+    }
+
+    #[test]
+    fn filters() {
+        let data = link();
+        assert!(LinkSearchRequest::default().matches(&data));
+
+        let by_tag = |tag: &str| LinkSearchRequest {
+            tag: Some(tag.to_owned()),
+            ..Default::default()
+        };
+        assert!(by_tag("email").matches(&data));
+        assert!(by_tag(" EMAIL").matches(&data));
+        assert!(!by_tag("print").matches(&data));
+
+        let by_q = |q: &str| LinkSearchRequest {
+            q: Some(q.to_owned()),
+            ..Default::default()
+        };
+        assert!(by_q("spring-sale").matches(&data));
+        assert!(by_q("june").matches(&data));
+        assert!(!by_q("july").matches(&data));
+
+        let by_date = |from: &str, to: &str| LinkSearchRequest {
+            from_date: Some(at(from)),
+            to_date: Some(at(to)),
+            ..Default::default()
+        };
+        assert!(by_date("2020-06-01T00:00:00Z", "2020-06-02T00:00:00Z").matches(&data));
+        assert!(!by_date("2020-05-01T00:00:00Z", "2020-06-01T00:00:00Z").matches(&data));
+        assert!(!by_date("2020-05-01T00:00:00Z", "2020-07-01T00:00:00Z")
+            .matches(&MicroUrlData::new("https://example.com")));
+    }
+}
@@ -1 +1,2 @@
+pub mod link_search;
 pub mod views;
@@ -6,7 +6,8 @@ use chrono::{DateTime, Duration, DurationRound, Utc};
 use fehler::*;
 use itertools::Itertools as _;
 use serde_json::Value;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,6 +15,20 @@ pub struct ViewsRequest {
     from_date: DateTime<Utc>,
     to_date: DateTime<Utc>,
     group_by_duration: Option<String>,
+    /// only count events for this micro url id
+    id: Option<String>,
+    /// only count events for the micro urls of this workspace
+    workspace: Option<String>,
+}
+
+impl ViewsRequest {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn workspace(&self) -> Option<&str> {
+        self.workspace.as_deref()
+    }
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -25,6 +40,15 @@ pub struct ViewsData {
 pub struct ViewsDataRow {
     date: DateTime<Utc>,
     views: usize,
+    conversions: usize,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    variants: BTreeMap<u64, ViewsVariantRow>,
+}
+
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct ViewsVariantRow {
+    views: usize,
+    conversions: usize,
 }
 
 #[derive(Clone)]
@@ -37,12 +61,12 @@ impl ViewsDao {
         ViewsDao { event_reader }
     }
 
-    pub fn from_path(path: &PathBuf) -> Self {
+    pub fn from_path(path: &Path) -> Self {
         ViewsDao::new(EventReader::new(path))
     }
 
     #[throws(anyhow::Error)]
-    pub fn get_views_data(&self, request: &ViewsRequest) -> ViewsData {
+    pub fn get_views_data(&self, request: &ViewsRequest, ids: &HashSet<String>) -> ViewsData {
         let time_range = request.from_date.timestamp_millis()..request.to_date.timestamp_millis();
         let dur = request
             .group_by_duration
@@ -53,19 +77,37 @@ impl ViewsDao {
         let events = self.event_reader.iter::<Value>();
         let events = events
             .filter_map(|e| e.ok())
-            .filter(|r| r.category == "redirect")
-            .filter(|r| time_range.contains(&r.id.timestamp_millis()));
+            .filter(|r| r.category == "redirect" || r.category == "conversion")
+            .filter(|r| time_range.contains(&r.id.timestamp_millis()))
+            .filter(|r| r.event["id"].as_str().is_some_and(|id| ids.contains(id)));
 
-        let mut data = ViewsData {
-            rows: vec![],
-        };
+        let mut data = ViewsData { rows: vec![] };
         for (dt, group) in
             &events.group_by(|event| event.to_datetime().duration_trunc(dur).unwrap())
         {
-            data.rows.push(ViewsDataRow{
+            let mut row = ViewsDataRow {
                 date: dt,
-                views: group.count(),
-            });
+                views: 0,
+                conversions: 0,
+                variants: BTreeMap::new(),
+            };
+            for event in group {
+                let is_view = event.category == "redirect";
+                if is_view {
+                    row.views += 1;
+                } else {
+                    row.conversions += 1;
+                }
+                if let Some(variant) = event.event["variant"].as_u64() {
+                    let variant_row = row.variants.entry(variant).or_default();
+                    if is_view {
+                        variant_row.views += 1;
+                    } else {
+                        variant_row.conversions += 1;
+                    }
+                }
+            }
+            data.rows.push(row);
         }
         data
     }
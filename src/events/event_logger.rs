@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use async_std::fs::File;
 use async_std::io::BufWriter;
@@ -29,14 +29,14 @@ pub struct EventLoggerOutputState {
 impl EventLogger {
     #[throws(anyhow::Error)]
     pub async fn new(
-        folder: &PathBuf,
+        folder: &Path,
         app: &str,
         ulid_generator: Arc<Mutex<UlidGenerator>>,
     ) -> EventLogger {
         let prev_ulid = Ulid::default();
         let state = Arc::new(Mutex::new(EventLoggerOutputState {
             prev_ulid,
-            folder: folder.clone(),
+            folder: folder.to_path_buf(),
             file: None,
         }));
         let logger_id = ulid_generator.lock().await.generate()?;
@@ -56,7 +56,7 @@ impl EventLogger {
 
         if prev != now || state.file.is_none() {
             let mut file = state.folder.clone();
-            file.push(now.to_string());
+            file.push(now);
             std::fs::create_dir_all(&file)?;
             state.prev_ulid = ulid;
             file.push(format!(
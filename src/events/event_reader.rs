@@ -1,6 +1,6 @@
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use serde::de::DeserializeOwned;
 use serde_json::Deserializer;
@@ -19,9 +19,9 @@ pub struct EventReader {
 }
 
 impl EventReader {
-    pub fn new(folder: &PathBuf) -> EventReader {
+    pub fn new(folder: &Path) -> EventReader {
         EventReader {
-            folder: folder.clone(),
+            folder: folder.to_path_buf(),
         }
     }
 
@@ -6,11 +6,12 @@ use lazy_static::lazy_static;
 use serde::export::Formatter;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(PartialOrd, Ord, PartialEq, Eq, Hash, Clone, Copy, Default)]
 pub struct Ulid {
     inner: u128,
 }
 
+#[derive(Default)]
 pub struct UlidGenerator {
     previous: Ulid,
 }
@@ -134,17 +135,9 @@ impl Ulid {
     }
 }
 
-impl Default for Ulid {
-    fn default() -> Self {
-        Ulid {
-            inner: u128::default(),
-        }
-    }
-}
-
 impl fmt::Debug for Ulid {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self)
     }
 }
 
@@ -199,14 +192,6 @@ impl<'de> Deserialize<'de> for Ulid {
     }
 }
 
-impl Default for UlidGenerator {
-    fn default() -> Self {
-        UlidGenerator {
-            previous: Ulid::default(),
-        }
-    }
-}
-
 impl UlidGenerator {
     pub fn new() -> UlidGenerator {
         UlidGenerator::default()
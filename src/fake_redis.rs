@@ -0,0 +1,296 @@
+//! Just enough of a redis server for the handler tests, keys never expire.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone)]
+enum Value {
+    Str(String),
+    Set(BTreeSet<String>),
+    Hash(HashMap<String, String>),
+}
+
+#[derive(Debug)]
+enum Reply {
+    Ok,
+    Status(&'static str),
+    Nil,
+    Int(i64),
+    Bulk(String),
+    Array(Vec<Reply>),
+    NilArray,
+    Error(String),
+}
+
+impl Reply {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Ok => out.extend(b"+OK\r\n"),
+            Reply::Status(s) => out.extend(format!("+{}\r\n", s).as_bytes()),
+            Reply::Nil => out.extend(b"$-1\r\n"),
+            Reply::Int(n) => out.extend(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(s) => {
+                out.extend(format!("${}\r\n", s.len()).as_bytes());
+                out.extend(s.as_bytes());
+                out.extend(b"\r\n");
+            }
+            Reply::Array(items) => {
+                out.extend(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write(out);
+                }
+            }
+            Reply::NilArray => out.extend(b"*-1\r\n"),
+            Reply::Error(e) => out.extend(format!("-{}\r\n", e).as_bytes()),
+        }
+    }
+}
+
+fn wrong_type() -> Reply {
+    Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned())
+}
+
+#[derive(Default)]
+struct Store {
+    values: HashMap<String, Value>,
+    /// bumped on every write, for WATCH
+    versions: HashMap<String, u64>,
+}
+
+impl Store {
+    fn touch(&mut self, key: &str) {
+        *self.versions.entry(key.to_owned()).or_insert(0) += 1;
+    }
+
+    fn version(&self, key: &str) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn run(&mut self, args: &[String]) -> Reply {
+        let name = args[0].to_ascii_uppercase();
+        let key = args.get(1).cloned().unwrap_or_default();
+        match name.as_str() {
+            "PING" => Reply::Status("PONG"),
+            "GET" => match self.values.get(&key) {
+                Some(Value::Str(s)) => Reply::Bulk(s.to_owned()),
+                Some(_) => wrong_type(),
+                None => Reply::Nil,
+            },
+            "MGET" => Reply::Array(
+                args[1..]
+                    .iter()
+                    .map(|k| match self.values.get(k) {
+                        Some(Value::Str(s)) => Reply::Bulk(s.to_owned()),
+                        _ => Reply::Nil,
+                    })
+                    .collect(),
+            ),
+            "SET" | "SETNX" | "SETEX" => {
+                let (value, options) = match name.as_str() {
+                    "SETEX" => (args[3].to_owned(), vec![]),
+                    _ => (
+                        args[2].to_owned(),
+                        args[3..].iter().map(|a| a.to_ascii_uppercase()).collect(),
+                    ),
+                };
+                let exists = self.values.contains_key(&key);
+                let nx = name == "SETNX" || options.iter().any(|o| o == "NX");
+                let xx = options.iter().any(|o| o == "XX");
+                if (nx && exists) || (xx && !exists) {
+                    return if name == "SETNX" {
+                        Reply::Int(0)
+                    } else {
+                        Reply::Nil
+                    };
+                }
+                self.values.insert(key.to_owned(), Value::Str(value));
+                self.touch(&key);
+                if name == "SETNX" {
+                    Reply::Int(1)
+                } else {
+                    Reply::Ok
+                }
+            }
+            "DEL" => {
+                let mut deleted = 0;
+                for k in &args[1..] {
+                    if self.values.remove(k).is_some() {
+                        deleted += 1;
+                        self.touch(k);
+                    }
+                }
+                Reply::Int(deleted)
+            }
+            "INCR" => {
+                let n = match self.values.get(&key) {
+                    Some(Value::Str(s)) => match s.parse::<i64>() {
+                        Ok(n) => n + 1,
+                        Err(_) => return Reply::Error("ERR not an integer".to_owned()),
+                    },
+                    Some(_) => return wrong_type(),
+                    None => 1,
+                };
+                self.values
+                    .insert(key.to_owned(), Value::Str(n.to_string()));
+                self.touch(&key);
+                Reply::Int(n)
+            }
+            "SADD" | "SREM" => {
+                let set = match self
+                    .values
+                    .entry(key.to_owned())
+                    .or_insert_with(|| Value::Set(BTreeSet::new()))
+                {
+                    Value::Set(set) => set,
+                    _ => return wrong_type(),
+                };
+                let changed = args[2..]
+                    .iter()
+                    .filter(|m| match name.as_str() {
+                        "SADD" => set.insert(m.to_string()),
+                        _ => set.remove(m.as_str()),
+                    })
+                    .count();
+                self.touch(&key);
+                Reply::Int(changed as i64)
+            }
+            "SMEMBERS" => match self.values.get(&key) {
+                Some(Value::Set(set)) => {
+                    Reply::Array(set.iter().map(|m| Reply::Bulk(m.to_owned())).collect())
+                }
+                Some(_) => wrong_type(),
+                None => Reply::Array(vec![]),
+            },
+            "HGET" => match self.values.get(&key) {
+                Some(Value::Hash(hash)) => match hash.get(&args[2]) {
+                    Some(v) => Reply::Bulk(v.to_owned()),
+                    None => Reply::Nil,
+                },
+                Some(_) => wrong_type(),
+                None => Reply::Nil,
+            },
+            "HSET" | "HDEL" => {
+                let hash = match self
+                    .values
+                    .entry(key.to_owned())
+                    .or_insert_with(|| Value::Hash(HashMap::new()))
+                {
+                    Value::Hash(hash) => hash,
+                    _ => return wrong_type(),
+                };
+                let changed = match name.as_str() {
+                    "HSET" => hash
+                        .insert(args[2].to_owned(), args[3].to_owned())
+                        .is_none(),
+                    _ => hash.remove(&args[2]).is_some(),
+                };
+                self.touch(&key);
+                Reply::Int(changed as i64)
+            }
+            _ => Reply::Error(format!("ERR unknown command '{}'", name)),
+        }
+    }
+}
+
+/// One RESP array of bulk strings, none once the client is gone.
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let len: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+        let mut buf = vec![0; len + 2];
+        reader.read_exact(&mut buf).ok()?;
+        buf.truncate(len);
+        args.push(String::from_utf8(buf).ok()?);
+    }
+    Some(args)
+}
+
+fn serve(stream: UnixStream, store: Arc<Mutex<Store>>) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut watched: HashMap<String, u64> = HashMap::new();
+    let mut queued: Option<Vec<Vec<String>>> = None;
+    while let Some(args) = read_command(&mut reader) {
+        if args.is_empty() {
+            break;
+        }
+        let mut store = store.lock().unwrap();
+        let reply = match (args[0].to_ascii_uppercase().as_str(), queued.as_mut()) {
+            ("WATCH", _) => {
+                for k in &args[1..] {
+                    watched.insert(k.to_owned(), store.version(k));
+                }
+                Reply::Ok
+            }
+            ("UNWATCH", _) => {
+                watched.clear();
+                Reply::Ok
+            }
+            ("MULTI", _) => {
+                queued = Some(vec![]);
+                Reply::Ok
+            }
+            ("DISCARD", _) => {
+                queued = None;
+                watched.clear();
+                Reply::Ok
+            }
+            ("EXEC", Some(_)) => {
+                let commands = queued.take().unwrap_or_default();
+                let changed = watched.iter().any(|(k, v)| store.version(k) != *v);
+                watched.clear();
+                if changed {
+                    Reply::NilArray
+                } else {
+                    Reply::Array(commands.iter().map(|c| store.run(c)).collect())
+                }
+            }
+            (_, Some(commands)) => {
+                commands.push(args);
+                Reply::Status("QUEUED")
+            }
+            _ => store.run(&args),
+        };
+        let mut out = vec![];
+        reply.write(&mut out);
+        if writer.write_all(&out).is_err() {
+            break;
+        }
+    }
+}
+
+/// Starts a server on a unix socket in a new temporary folder, its url.
+pub fn start() -> String {
+    let path = tempfile::tempdir().unwrap().into_path().join("redis.sock");
+    let listener = UnixListener::bind(&path).unwrap();
+    let store = Arc::new(Mutex::new(Store::default()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let store = store.clone();
+            std::thread::spawn(move || serve(stream.unwrap(), store));
+        }
+    });
+    format!("redis+unix://{}", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::AsyncCommands;
+
+    #[async_std::test]
+    async fn commands() {
+        let client = redis::Client::open(start()).unwrap();
+        let mut con = client.get_async_connection().await.unwrap();
+        con.set::<_, _, ()>("a", "1").await.unwrap();
+        let a: Option<String> = con.get("a").await.unwrap();
+        assert_eq!(a.as_deref(), Some("1"));
+    }
+}
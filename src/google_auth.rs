@@ -1,44 +1,163 @@
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+/// Google rotates its keys every few days, they are fetched again at most this often.
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+/// Checked tokens remembered until they expire, beyond this the expired ones are dropped.
+const MAX_CACHED_CLAIMS: usize = 10_000;
 
-#[derive(Debug, Serialize, Deserialize)]
+lazy_static! {
+    static ref JWKS: Mutex<Option<(Instant, Vec<serde_json::Value>)>> = Mutex::new(None);
+    /// by sha256 of the token
+    static ref CLAIMS: Mutex<HashMap<String, GoogleClaims>> = Mutex::new(HashMap::new());
+}
+
+/// Google signs its id tokens as either.
+const ISSUERS: &[&str] = &["accounts.google.com", "https://accounts.google.com"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GoogleClaims {
     pub email: String,
     pub email_verified: bool,
     pub name: String,
     exp: usize,
+    /// the oauth client the token was issued to
+    aud: String,
+    iss: String,
+}
+
+impl GoogleClaims {
+    fn is_expired(&self) -> bool {
+        self.exp as i64 <= chrono::Utc::now().timestamp()
+    }
+
+    /// Whether google vouches for the email to us, not to some other client.
+    fn is_accepted(&self, client_id: &str) -> bool {
+        self.email_verified
+            && self.aud == client_id
+            && ISSUERS.contains(&self.iss.as_str())
+            && !self.is_expired()
+    }
 }
 
-pub fn get_claim_from_google(token: &str) -> Option<GoogleClaims> {
+/// Google's signing keys, from the cache while it is fresh. Blocks.
+fn google_keys() -> Option<Vec<serde_json::Value>> {
+    if let Some((fetched, ref keys)) = *JWKS.lock().unwrap() {
+        if fetched.elapsed() < JWKS_TTL {
+            return Some(keys.clone());
+        }
+    }
     let resp = ureq::get("https://www.googleapis.com/oauth2/v3/certs").call();
     if let Some(err) = resp.synthetic_error() {
         error!("unable to make request. {}", err);
-        return None
-    }
-
-    assert!(resp.ok());
-    if let Ok(j) = resp.into_json() {
-        for jwk in j["keys"].as_array().unwrap() {
-            if let Ok(alg) = Algorithm::from_str(jwk["alg"].as_str().unwrap()) {
-                let token = decode::<GoogleClaims>(
-                    &token,
-                    &DecodingKey::from_rsa_components(
-                        jwk["n"].as_str().unwrap(),
-                        jwk["e"].as_str().unwrap(),
-                    ),
-                    &Validation::new(alg),
-                );
-                match token {
-                    Ok(t) => return Some(t.claims),
-                    Err(e) => debug!("unable to validate {:?}", e),
-                }
+        return None;
+    }
+    if !resp.ok() {
+        error!("unable to get google keys, status {}", resp.status());
+        return None;
+    }
+    match resp.into_json() {
+        Ok(j) => {
+            let keys = j["keys"].as_array().cloned().unwrap_or_default();
+            *JWKS.lock().unwrap() = Some((Instant::now(), keys.clone()));
+            Some(keys)
+        }
+        Err(_) => {
+            error!("unable to deserialize json");
+            None
+        }
+    }
+}
+
+/// Checks the id token against google's keys. Blocks.
+fn get_claim_from_google(token: &str, client_id: &str) -> Option<GoogleClaims> {
+    for jwk in google_keys()? {
+        let alg = jwk["alg"].as_str().map(Algorithm::from_str);
+        if let (Some(Ok(alg)), Some(n), Some(e)) = (alg, jwk["n"].as_str(), jwk["e"].as_str()) {
+            let mut validation = Validation::new(alg);
+            validation.set_audience(&[client_id]);
+            let token =
+                decode::<GoogleClaims>(token, &DecodingKey::from_rsa_components(n, e), &validation);
+            match token {
+                Ok(t) => return Some(t.claims),
+                Err(e) => debug!("unable to validate {:?}", e),
             }
         }
-    } else {
-        error!("unable to deserialize json")
     }
     None
 }
+
+/// `get_claim_from_google` off the async threads, remembering the tokens it checked. None unless
+/// the token was issued to `client_id` and its email is verified.
+pub async fn google_claims(token: &str, client_id: Option<&str>) -> Option<GoogleClaims> {
+    let client_id = client_id?.to_owned();
+    let token_hash = hex::encode(Sha256::digest(token.as_bytes()));
+    if let Some(claims) = CLAIMS.lock().unwrap().get(&token_hash) {
+        if !claims.is_expired() {
+            return Some(claims.clone()).filter(|c| c.is_accepted(&client_id));
+        }
+    }
+    let token = token.to_owned();
+    let claims = async_std::task::spawn_blocking({
+        let client_id = client_id.to_owned();
+        move || get_claim_from_google(&token, &client_id)
+    })
+    .await?;
+    if !claims.is_accepted(&client_id) {
+        warn!("google token for [{}] not accepted", claims.email);
+        return None;
+    }
+    remember(token_hash, claims.clone());
+    Some(claims)
+}
+
+fn remember(token_hash: String, claims: GoogleClaims) {
+    let mut cached = CLAIMS.lock().unwrap();
+    if cached.len() >= MAX_CACHED_CLAIMS {
+        cached.retain(|_, c| !c.is_expired());
+    }
+    if cached.len() < MAX_CACHED_CLAIMS {
+        cached.insert(token_hash, claims);
+    }
+}
+
+/// Claims as if google had signed `token`, for tests that can not reach google.
+#[cfg(test)]
+pub fn fake_token(token: &str, email: &str, email_verified: bool, client_id: &str) {
+    let claims = GoogleClaims {
+        email: email.to_owned(),
+        email_verified,
+        name: email.to_owned(),
+        exp: (chrono::Utc::now().timestamp() + 3600) as usize,
+        aud: client_id.to_owned(),
+        iss: ISSUERS[0].to_owned(),
+    };
+    remember(hex::encode(Sha256::digest(token.as_bytes())), claims);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn accepted_claims() {
+        fake_token("verified", "a@example.com", true, "us");
+        fake_token("unverified", "b@example.com", false, "us");
+        fake_token("other", "c@example.com", true, "someone-else");
+        let email = |c: Option<GoogleClaims>| c.map(|c| c.email);
+        assert_eq!(
+            email(google_claims("verified", Some("us")).await).as_deref(),
+            Some("a@example.com")
+        );
+        assert!(google_claims("verified", None).await.is_none());
+        assert!(google_claims("unverified", Some("us")).await.is_none());
+        assert!(google_claims("other", Some("us")).await.is_none());
+    }
+}
@@ -0,0 +1,351 @@
+use std::time::Duration as StdDuration;
+
+use async_std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, Utc};
+use fehler::*;
+
+use crate::dao::health_dao::{HealthDao, LinkHealth};
+use crate::dao::url_dao::UrlDao;
+use crate::events::event_logger::EventLogger;
+use crate::metadata::PublicResolver;
+
+/// Failing destinations are checked at most `2^MAX_BACKOFF` intervals apart.
+const MAX_BACKOFF: u32 = 4;
+
+/// What a destination answered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// Redirects followed before a destination counts as failing.
+const MAX_REDIRECTS: usize = 5;
+
+/// Asks for `url` with a HEAD, or a GET when HEAD is not supported, following redirects a hop at
+/// a time so that every hop has to be on a public address unless `allow_private`. Blocks.
+pub fn probe(url: &str, timeout: StdDuration, allow_private: bool) -> Probe {
+    let mut agent = ureq::agent();
+    if !allow_private {
+        agent.set_resolver(PublicResolver);
+    }
+    let mut url = url.to_owned();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut response = agent.head(&url).timeout(timeout).redirects(0).call();
+        if matches!(response.status(), 405 | 501) && response.synthetic_error().is_none() {
+            response = agent.get(&url).timeout(timeout).redirects(0).call();
+        }
+        if let Some(e) = response.synthetic_error() {
+            return Probe {
+                status: None,
+                error: Some(e.to_string()),
+            };
+        }
+        let next = response
+            .header("location")
+            .filter(|_| response.redirect())
+            .and_then(|l| http_types::Url::parse(&url).ok()?.join(l).ok())
+            .filter(|u| matches!(u.scheme(), "http" | "https"));
+        match next {
+            Some(next) => url = next.to_string(),
+            None => {
+                return Probe {
+                    status: Some(response.status()),
+                    error: None,
+                }
+            }
+        }
+    }
+    Probe {
+        status: None,
+        error: Some(format!("more than {} redirects", MAX_REDIRECTS)),
+    }
+}
+
+impl LinkHealth {
+    /// The health after `probe`, checked again sooner when healthy and later the more it failed.
+    pub fn after(
+        previous: Option<&LinkHealth>,
+        long_url: &str,
+        probe: Probe,
+        now: DateTime<Utc>,
+        interval: Duration,
+    ) -> LinkHealth {
+        let healthy = probe.status.is_some_and(|s| (200..400).contains(&s));
+        let failures = match previous {
+            _ if healthy => 0,
+            Some(p) if p.long_url == long_url => p.failures + 1,
+            _ => 1,
+        };
+        LinkHealth {
+            long_url: long_url.to_owned(),
+            checked_at: now,
+            healthy,
+            status: probe.status,
+            error: probe.error,
+            failures,
+            next_check_at: now + interval * 2_i32.pow(failures.min(MAX_BACKOFF)),
+        }
+    }
+
+    /// Whether the checker should look at `long_url` again, a changed destination is due at once.
+    pub fn is_due(&self, long_url: &str, now: DateTime<Utc>) -> bool {
+        self.long_url != long_url || self.next_check_at <= now
+    }
+
+    fn changed_from(&self, previous: Option<&LinkHealth>) -> bool {
+        previous.is_none_or(|p| {
+            p.long_url != self.long_url || p.healthy != self.healthy || p.status != self.status
+        })
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HealthEvent<'a> {
+    id: &'a str,
+    health: &'a LinkHealth,
+}
+
+/// Periodically checks the destinations of the active micro urls without a password. Only the
+/// micro urls in the links set are checked, those created before the set was kept are not.
+#[derive(Clone)]
+pub struct HealthChecker {
+    pub url_dao: UrlDao,
+    pub health_dao: HealthDao,
+    pub event_logger: EventLogger,
+    pub interval: Duration,
+    pub concurrency: usize,
+    pub timeout: StdDuration,
+    /// for local development and tests only
+    pub allow_private: bool,
+}
+
+impl HealthChecker {
+    /// Looks for due destinations every minute, forever.
+    pub fn spawn(self) {
+        async_std::task::spawn(async move {
+            loop {
+                if let Err(e) = self.check_due().await {
+                    warn!("unable to check link health, {}", e);
+                }
+                async_std::task::sleep(StdDuration::from_secs(60)).await;
+            }
+        });
+    }
+
+    #[throws(anyhow::Error)]
+    async fn check_due(&self) {
+        let ids: Vec<String> = self.url_dao.all_links().await?.into_iter().collect();
+        let queue = Arc::new(Mutex::new(ids));
+        let workers: Vec<_> = (0..self.concurrency.max(1))
+            .map(|_| {
+                let checker = self.clone();
+                let queue = queue.clone();
+                async_std::task::spawn(async move {
+                    loop {
+                        let id = queue.lock().await.pop();
+                        match id {
+                            Some(id) => {
+                                if let Err(e) = checker.check(&id).await {
+                                    warn!("unable to check health of [{}], {}", id, e);
+                                }
+                            }
+                            None => break,
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.await;
+        }
+    }
+
+    #[throws(anyhow::Error)]
+    async fn check(&self, id: &str) {
+        let now = Utc::now();
+        // where protected and not yet active links go is not written down anywhere
+        let data = match self.url_dao.get_micro_url(id).await? {
+            Some(data) if data.is_active(now) && data.password_hash.is_none() => data,
+            _ => return,
+        };
+        let long_url = data.long_url_at(now).to_owned();
+        let previous = self.health_dao.get_health(id).await?;
+        if previous.as_ref().is_some_and(|p| !p.is_due(&long_url, now)) {
+            return;
+        }
+
+        let url = long_url.to_owned();
+        let (timeout, allow_private) = (self.timeout, self.allow_private);
+        let probe =
+            async_std::task::spawn_blocking(move || probe(&url, timeout, allow_private)).await;
+        let health = LinkHealth::after(previous.as_ref(), &long_url, probe, now, self.interval);
+        self.health_dao.set_health(id, &health).await?;
+        if health.changed_from(previous.as_ref()) {
+            let event = HealthEvent {
+                id,
+                health: &health,
+            };
+            self.event_logger.log_event("health", &event).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Answers every request with `status`, and says which methods it was asked with.
+    fn stub(status: u16, requests: usize) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut methods = vec![];
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                methods.push(request.split(' ').next().unwrap_or("").to_owned());
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Stub\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+            methods
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn probes() {
+        let timeout = StdDuration::from_secs(5);
+        let (url, stub_handle) = stub(200, 1);
+        assert_eq!(probe(&url, timeout, true).status, Some(200));
+        assert_eq!(stub_handle.join().unwrap(), vec!["HEAD"]);
+
+        let (url, stub_handle) = stub(404, 1);
+        assert_eq!(probe(&url, timeout, true).status, Some(404));
+        stub_handle.join().unwrap();
+
+        // falls back to a GET when HEAD is not allowed
+        let (url, stub_handle) = stub(405, 2);
+        assert_eq!(probe(&url, timeout, true).status, Some(405));
+        assert_eq!(stub_handle.join().unwrap(), vec!["HEAD", "GET"]);
+
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+        let refused = probe(&url, timeout, true);
+        assert_eq!(refused.status, None);
+        assert!(refused.error.is_some());
+
+        // follows redirects itself
+        let (target, stub_handle) = stub(404, 1);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut stream = listener.incoming().next().unwrap().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 301 Stub\r\nlocation: {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                target
+            );
+        });
+        assert_eq!(probe(&url, timeout, true).status, Some(404));
+        stub_handle.join().unwrap();
+
+        // never connects to a loopback address
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let private = probe(&url, timeout, false);
+        assert_eq!(private.status, None);
+        assert!(private.error.unwrap().contains("not a public address"));
+    }
+
+    #[async_std::test]
+    async fn skips_protected_links() {
+        use crate::dao::url_dao::MicroUrlData;
+        use crate::events::ulid::UlidGenerator;
+        use structopt::StructOpt;
+
+        let mut app_config = crate::AppConfig::from_iter(&[crate::APP_NAME]);
+        app_config.redis_urls_client_conn = crate::fake_redis::start();
+        let folder = tempfile::tempdir().unwrap().into_path();
+        let ulid_generator = Arc::new(Mutex::new(UlidGenerator::new()));
+        let checker = HealthChecker {
+            url_dao: UrlDao::new(&app_config).unwrap(),
+            health_dao: HealthDao::new(&app_config.redis_urls_client_conn).unwrap(),
+            event_logger: EventLogger::new(&folder, "test", ulid_generator)
+                .await
+                .unwrap(),
+            interval: Duration::hours(1),
+            concurrency: 1,
+            timeout: StdDuration::from_secs(5),
+            allow_private: true,
+        };
+        let (url, stub_handle) = stub(200, 1);
+        let plain = MicroUrlData::new(&url);
+        let protected = MicroUrlData {
+            password_hash: Some("hash".to_owned()),
+            ..plain.clone()
+        };
+        let not_yet = MicroUrlData {
+            not_before: Some(Utc::now() + Duration::days(1)),
+            ..plain.clone()
+        };
+        for (data, checked) in &[(plain, true), (protected, false), (not_yet, false)] {
+            let id = checker
+                .url_dao
+                .create_micro_url(data, None)
+                .await
+                .unwrap()
+                .unwrap()
+                .id;
+            checker.check(&id).await.unwrap();
+            let health = checker.health_dao.get_health(&id).await.unwrap();
+            assert_eq!(health.is_some(), *checked, "{:?}", data);
+        }
+        assert_eq!(stub_handle.join().unwrap(), vec!["HEAD"]);
+    }
+
+    #[test]
+    fn backoff() {
+        let now: DateTime<Utc> = "2020-06-01T00:00:00Z".parse().unwrap();
+        let interval = Duration::hours(1);
+        let failed = Probe {
+            status: Some(503),
+            error: None,
+        };
+
+        let first = LinkHealth::after(None, "https://a", failed.clone(), now, interval);
+        assert!(!first.healthy);
+        assert_eq!(first.failures, 1);
+        assert_eq!(first.next_check_at, now + Duration::hours(2));
+        assert!(!first.is_due("https://a", now + Duration::hours(1)));
+        assert!(first.is_due("https://b", now));
+
+        let mut health = first;
+        for _ in 0..10 {
+            health = LinkHealth::after(Some(&health), "https://a", failed.clone(), now, interval);
+        }
+        assert_eq!(health.failures, 11);
+        assert_eq!(health.next_check_at, now + Duration::hours(16));
+
+        let ok = Probe {
+            status: Some(200),
+            error: None,
+        };
+        let recovered = LinkHealth::after(Some(&health), "https://a", ok, now, interval);
+        assert!(recovered.healthy);
+        assert_eq!(recovered.failures, 0);
+        assert_eq!(recovered.next_check_at, now + interval);
+        assert!(recovered.changed_from(Some(&health)));
+        assert!(!recovered.changed_from(Some(&recovered)));
+    }
+}
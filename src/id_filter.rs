@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use fehler::*;
+use tide::utils::async_trait;
+
+use crate::id_generator::{DecodedId, IdGenerator};
+
+/// Ids tried before giving up on finding a clean one.
+const MAX_ATTEMPTS: u32 = 50;
+
+/// Blocked when no word list is configured, matched with their leetspeak spellings.
+const DEFAULT_BLOCKED_WORDS: &[&str] = &[
+    "anal", "anus", "arse", "ass", "bitch", "boob", "butt", "cock", "crap", "cum", "cunt", "dick",
+    "dildo", "fag", "fuck", "hell", "jizz", "kkk", "nazi", "nude", "penis", "piss", "porn", "poop",
+    "pussy", "rape", "sex", "shit", "slut", "tit", "twat", "vagina", "wank", "whore",
+];
+
+/// Easy to mistake for each other when printed.
+const AMBIGUOUS: &str = "0Oo1lI";
+
+/// Whether a character of an id can be read as `letter`, digits as the letters they look like.
+fn reads_as(c: char, letter: char) -> bool {
+    let c = c.to_ascii_lowercase();
+    c == letter
+        || matches!(
+            (c, letter),
+            ('0', 'o')
+                | ('1', 'i')
+                | ('1', 'l')
+                | ('2', 'z')
+                | ('3', 'e')
+                | ('4', 'a')
+                | ('5', 's')
+                | ('6', 'g')
+                | ('7', 't')
+                | ('8', 'b')
+                | ('9', 'g')
+        )
+}
+
+/// Which generated ids are not handed out.
+#[derive(Debug, Clone)]
+pub struct IdFilter {
+    /// lower case, without the leetspeak
+    words: Vec<String>,
+    exclude_ambiguous: bool,
+}
+
+impl IdFilter {
+    pub fn new(words: &[&str], exclude_ambiguous: bool) -> IdFilter {
+        IdFilter {
+            words: words
+                .iter()
+                .map(|w| w.trim().to_lowercase())
+                .filter(|w| !w.is_empty())
+                .collect(),
+            exclude_ambiguous,
+        }
+    }
+
+    /// Reads a word per line from `path`, `#` starts a comment, the default words when none.
+    #[throws(anyhow::Error)]
+    pub fn load(path: Option<&Path>, exclude_ambiguous: bool) -> IdFilter {
+        match path {
+            Some(path) => {
+                let s = std::fs::read_to_string(path)?;
+                let words: Vec<&str> = s
+                    .lines()
+                    .map(|l| l.split('#').next().unwrap_or(""))
+                    .collect();
+                let filter = IdFilter::new(&words, exclude_ambiguous);
+                info!(
+                    "loaded {} blocked id words from [{}]",
+                    filter.words.len(),
+                    path.display()
+                );
+                filter
+            }
+            None => IdFilter::new(DEFAULT_BLOCKED_WORDS, exclude_ambiguous),
+        }
+    }
+
+    /// Whether `id` spells a blocked word, reading digits as letters and skipping `-` and `_`.
+    pub fn has_blocked_word(&self, id: &str) -> bool {
+        let chars: Vec<char> = id.chars().filter(|c| *c != '-' && *c != '_').collect();
+        self.words.iter().any(|word| {
+            let word: Vec<char> = word.chars().collect();
+            chars.windows(word.len()).any(|window| {
+                window
+                    .iter()
+                    .zip(&word)
+                    .all(|(c, letter)| reads_as(*c, *letter))
+            })
+        })
+    }
+
+    pub fn has_ambiguous(&self, id: &str) -> bool {
+        self.exclude_ambiguous && id.contains(|c| AMBIGUOUS.contains(c))
+    }
+}
+
+/// Generates ids with another generator until one passes the filter. Ids are only ever
+/// regenerated, never changed, so whatever the scheme encodes in them stays intact.
+pub struct FilteredIdGenerator {
+    inner: Box<dyn IdGenerator>,
+    filter: IdFilter,
+}
+
+impl FilteredIdGenerator {
+    pub fn new(inner: Box<dyn IdGenerator>, filter: IdFilter) -> FilteredIdGenerator {
+        FilteredIdGenerator { inner, filter }
+    }
+}
+
+#[async_trait]
+impl IdGenerator for FilteredIdGenerator {
+    async fn gen_id(&self) -> anyhow::Result<String> {
+        // an alphabet made mostly of ambiguous characters may not be able to avoid them, that is
+        // better than no id at all
+        let mut fallback = None;
+        for _ in 0..MAX_ATTEMPTS {
+            let id = self.inner.gen_id().await?;
+            if self.filter.has_blocked_word(&id) {
+                debug!("id [{}] spells a blocked word, trying another", id);
+            } else if self.filter.has_ambiguous(&id) {
+                fallback.get_or_insert(id);
+            } else {
+                return Ok(id);
+            }
+        }
+        match fallback {
+            Some(id) => {
+                warn!("no id without ambiguous characters, using [{}]", id);
+                Ok(id)
+            }
+            None => Err(anyhow::anyhow!(
+                "no id without a blocked word after {} attempts",
+                MAX_ATTEMPTS
+            )),
+        }
+    }
+
+    fn decode(&self, id: &str) -> Option<DecodedId> {
+        self.inner.decode(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id_generator::TimeIdGenerator;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn words() {
+        let filter = IdFilter::new(&["fuck", "shit", " Ass "], false);
+        assert!(filter.has_blocked_word("xxfuckxx"));
+        assert!(filter.has_blocked_word("xxFUCKxx"));
+        assert!(filter.has_blocked_word("5h1t"));
+        assert!(filter.has_blocked_word("aa_5h-1t"));
+        assert!(filter.has_blocked_word("q455"));
+        assert!(!filter.has_blocked_word("a5x5"));
+        assert!(!filter.has_blocked_word("pm1otTq7"));
+        assert!(!filter.has_ambiguous("pm1otTq7"));
+
+        let filter = IdFilter::new(&[], true);
+        assert!(filter.has_ambiguous("pm1otTq7"));
+        assert!(filter.has_ambiguous("abcO"));
+        assert!(!filter.has_ambiguous("abc2"));
+
+        assert!(IdFilter::load(None, false)
+            .unwrap()
+            .has_blocked_word("5h1t"));
+    }
+
+    /// Hands out the given ids in order.
+    struct Fixed(Vec<&'static str>, AtomicUsize);
+
+    #[async_trait]
+    impl IdGenerator for Fixed {
+        async fn gen_id(&self) -> anyhow::Result<String> {
+            let n = self.1.fetch_add(1, Ordering::SeqCst);
+            Ok(self.0[n.min(self.0.len() - 1)].to_owned())
+        }
+    }
+
+    #[async_std::test]
+    async fn regenerates() {
+        let fixed = |ids| Box::new(Fixed(ids, AtomicUsize::new(0)));
+        let filter = IdFilter::new(&["shit"], true);
+
+        let generator =
+            FilteredIdGenerator::new(fixed(vec!["ab5h1t", "abc1", "abcd"]), filter.clone());
+        assert_eq!(generator.gen_id().await.unwrap(), "abcd");
+
+        let generator = FilteredIdGenerator::new(fixed(vec!["ab5h1t", "abc1"]), filter.clone());
+        assert_eq!(generator.gen_id().await.unwrap(), "abc1");
+
+        let generator = FilteredIdGenerator::new(fixed(vec!["ab5h1t"]), filter);
+        assert!(generator.gen_id().await.is_err());
+
+        // still a time id
+        let generator = FilteredIdGenerator::new(
+            Box::new(TimeIdGenerator::new(8)),
+            IdFilter::load(None, false).unwrap(),
+        );
+        let id = generator.gen_id().await.unwrap();
+        assert!(generator.decode(&id).is_some());
+    }
+}
@@ -1,17 +1,49 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure};
 use chrono::{DateTime, Duration, Utc};
+use fehler::*;
 use lazy_static::lazy_static;
 use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use tide::utils::async_trait;
 
-#[derive(Clone)]
-pub struct IdGenerator {
-    len: u8,
+/// Makes the ids of new micro urls.
+#[async_trait]
+pub trait IdGenerator: Send + Sync {
+    async fn gen_id(&self) -> anyhow::Result<String>;
+
+    /// What `id` says about how it was made, none when this scheme could not have made it or
+    /// keeps nothing in it.
+    fn decode(&self, _id: &str) -> Option<DecodedId> {
+        None
+    }
+}
+
+/// What is packed into an id.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum DecodedId {
+    Time {
+        created_at: DateTime<Utc>,
+        /// seconds since 2020-01-01
+        seconds: u32,
+        /// the random bits that fit in the id
+        random: u32,
+    },
+    Counter {
+        counter: u64,
+    },
 }
 
 #[allow(dead_code)] // reg alphabet for testing, to make it easier to understand
 const ALPHABET: &[u8; 64] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_-";
 // mix up the alphabet so that its harder for humans to see any patterns
-const CRAZY_ALPHABET: &[u8; 64] =
+pub const CRAZY_ALPHABET: &[u8; 64] =
     b"pm1otTq7cI4J9Oy_Fd2aQezXVU5S3gYlrxnv6fPshHGRDbWjkuiLZ80wBNA-KCME";
+// no 0/o, 1/l/i, lower case only, so ids can be read out and typed back
+pub const HUMAN_ALPHABET: &[u8; 31] = b"23456789abcdefghjkmnpqrstuvwxyz";
 // time constants
 const TIME_BITS: u32 = 30;
 lazy_static! {
@@ -27,18 +59,147 @@ lazy_static! {
     static ref MAX_TIME: DateTime<Utc> = *OUR_EPOCH + Duration::seconds(*MAX_SECONDS as i64);
 }
 
-impl IdGenerator {
-    pub fn new(len: u8) -> IdGenerator {
+/// Characters ids are made of, each used once and none that mean something in a path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alphabet(Vec<u8>);
+
+impl FromStr for Alphabet {
+    type Err = anyhow::Error;
+
+    #[throws(anyhow::Error)]
+    fn from_str(s: &str) -> Alphabet {
+        let chars = s.as_bytes().to_vec();
+        ensure!(chars.len() >= 2, "alphabet needs at least 2 characters");
+        for (i, c) in chars.iter().enumerate() {
+            ensure!(
+                c.is_ascii_alphanumeric() || *c == b'-' || *c == b'_',
+                "alphabet can only have letters, digits, - and _, not [{}]",
+                *c as char
+            );
+            ensure!(
+                !chars[..i].contains(c),
+                "alphabet has [{}] more than once",
+                *c as char
+            );
+        }
+        Alphabet(chars)
+    }
+}
+
+impl Alphabet {
+    fn base(&self) -> u128 {
+        self.0.len() as u128
+    }
+
+    /// `value` as exactly `len` characters, most significant first.
+    fn encode(&self, mut value: u128, len: usize) -> String {
+        let mut buf = vec![self.0[0]; len];
+        for c in buf.iter_mut().rev() {
+            *c = self.0[(value % self.base()) as usize];
+            value /= self.base();
+        }
+        String::from_utf8(buf).expect("unexpected failure in encode for id gen")
+    }
+
+    fn decode(&self, id: &str) -> Option<u128> {
+        id.bytes().try_fold(0_u128, |value, c| {
+            let digit = self.0.iter().position(|a| *a == c)? as u128;
+            value.checked_mul(self.base())?.checked_add(digit)
+        })
+    }
+}
+
+/// How ids are made, see the `IdGenerator` implementations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdScheme {
+    Time,
+    Random,
+    Counter,
+    Human,
+}
+
+impl FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    #[throws(anyhow::Error)]
+    fn from_str(s: &str) -> IdScheme {
+        match s {
+            "time" => IdScheme::Time,
+            "random" => IdScheme::Random,
+            "counter" => IdScheme::Counter,
+            "human" => IdScheme::Human,
+            _ => throw!(anyhow!(
+                "expected one of time, random, counter or human, got [{}]",
+                s
+            )),
+        }
+    }
+}
+
+/// Builds the generator for `scheme`, the alphabet defaults to one that suits the scheme.
+#[throws(anyhow::Error)]
+pub fn id_generator(
+    scheme: IdScheme,
+    len: u8,
+    alphabet: Option<Alphabet>,
+    redis_conn: &str,
+    counter_secret: Option<&str>,
+) -> Box<dyn IdGenerator> {
+    let alphabet = alphabet.unwrap_or_else(|| match scheme {
+        IdScheme::Human => Alphabet(HUMAN_ALPHABET.to_vec()),
+        _ => Alphabet(CRAZY_ALPHABET.to_vec()),
+    });
+    ensure!(len <= 20, "ids can be at most 20 characters, not {}", len);
+    match scheme {
+        IdScheme::Time => {
+            ensure!(len > 6, "time ids need more than 6 characters");
+            ensure!(
+                alphabet.0.len() == 64,
+                "time ids need an alphabet of exactly 64 characters"
+            );
+            let mut alpha = [0; 64];
+            alpha.copy_from_slice(&alphabet.0);
+            Box::new(TimeIdGenerator::with_alphabet(alpha, len)) as Box<dyn IdGenerator>
+        }
+        IdScheme::Random | IdScheme::Human => {
+            ensure!(len >= 4, "random ids need at least 4 characters");
+            Box::new(RandomIdGenerator { alphabet, len })
+        }
+        IdScheme::Counter => {
+            let secret = counter_secret
+                .ok_or_else(|| anyhow!("counter ids need a secret to obfuscate the counter"))?;
+            Box::new(CounterIdGenerator::new(
+                redis::Client::open(redis_conn)?,
+                Feistel::new(alphabet, len, secret)?,
+            ))
+        }
+    }
+}
+
+/// Seconds since 2020 in the first 6 characters, sortable by creation time, and random bits.
+#[derive(Clone)]
+pub struct TimeIdGenerator {
+    alpha: [u8; 64],
+    len: u8,
+}
+
+impl TimeIdGenerator {
+    #[cfg(test)]
+    pub fn new(len: u8) -> TimeIdGenerator {
+        TimeIdGenerator::with_alphabet(*CRAZY_ALPHABET, len)
+    }
+
+    pub fn with_alphabet(alpha: [u8; 64], len: u8) -> TimeIdGenerator {
         if len <= 6 {
             panic!("too small")
         }
         if len > 20 {
             panic!("too large")
         }
-        IdGenerator { len }
+        TimeIdGenerator { alpha, len }
     }
 
-    pub fn gen_id(&self) -> String {
+    fn gen(&self) -> String {
         let now = Utc::now();
         if now >= *MAX_TIME {
             panic!("something has gone very wrong, we are in {}", *OUR_EPOCH);
@@ -46,7 +207,31 @@ impl IdGenerator {
 
         let rn = thread_rng().gen::<u32>();
         let ts = now.signed_duration_since(*OUR_EPOCH).num_seconds() as u32;
-        id_from_ts_and_random(CRAZY_ALPHABET, self.len as usize, ts, rn)
+        id_from_ts_and_random(&self.alpha, self.len as usize, ts, rn)
+    }
+}
+
+#[async_trait]
+impl IdGenerator for TimeIdGenerator {
+    async fn gen_id(&self) -> anyhow::Result<String> {
+        Ok(self.gen())
+    }
+
+    fn decode(&self, id: &str) -> Option<DecodedId> {
+        // aliases of other lengths would decode to a made up time
+        if id.len() != self.len as usize {
+            return None;
+        }
+        let (seconds, random) = ts_and_random_from_id(&self.alpha, id)?;
+        let created_at = *OUR_EPOCH + Duration::seconds(seconds as i64);
+        if created_at > Utc::now() {
+            return None;
+        }
+        Some(DecodedId::Time {
+            created_at,
+            seconds,
+            random,
+        })
     }
 }
 
@@ -79,16 +264,196 @@ fn id_from_ts_and_random(alpha: &[u8; 64], len: usize, ts: u32, rn: u32) -> Stri
     String::from_utf8(buf).expect("unexpected failure in encode for id gen")
 }
 
+/// The inverse of `id_from_ts_and_random`, random bits that did not fit in `id` are 0.
+fn ts_and_random_from_id(alpha: &[u8; 64], id: &str) -> Option<(u32, u32)> {
+    const TIME_SHIFT: u32 = 5;
+    let num_time_chars = (TIME_BITS / TIME_SHIFT) as usize;
+    if id.len() <= num_time_chars {
+        return None;
+    }
+
+    let mut ts = 0_u32;
+    let mut rn = 0_u64;
+    for (i, c) in id.bytes().rev().enumerate() {
+        let v = alpha.iter().position(|a| *a == c)? as u64;
+        if i < num_time_chars {
+            ts |= ((v >> 1) as u32) << (TIME_SHIFT * i as u32);
+            rn |= (v & 0x01) << i;
+        } else {
+            let shift = (num_time_chars + 6 * (i - num_time_chars)) as u32;
+            // the random number only has 32 bits, past them the id is padding
+            if shift < 32 {
+                rn |= v << shift;
+            } else if v != 0 {
+                return None;
+            }
+        }
+    }
+    u32::try_from(rn).ok().map(|rn| (ts, rn))
+}
+
+/// Every character random, with the human alphabet this is the human friendly scheme.
+#[derive(Clone)]
+pub struct RandomIdGenerator {
+    alphabet: Alphabet,
+    len: u8,
+}
+
+impl RandomIdGenerator {
+    fn gen(&self) -> String {
+        let mut rng = thread_rng();
+        (0..self.len)
+            .map(|_| self.alphabet.0[rng.gen_range(0, self.alphabet.0.len())] as char)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl IdGenerator for RandomIdGenerator {
+    async fn gen_id(&self) -> anyhow::Result<String> {
+        Ok(self.gen())
+    }
+}
+
+const FEISTEL_ROUNDS: u8 = 4;
+
+/// A keyed permutation of all the ids of one length, so counters do not look like counters.
+#[derive(Clone)]
+pub struct Feistel {
+    alphabet: Alphabet,
+    len: usize,
+    /// number of ids of `len` characters
+    size: u128,
+    half_bits: u32,
+    key: Vec<u8>,
+}
+
+impl Feistel {
+    #[throws(anyhow::Error)]
+    pub fn new(alphabet: Alphabet, len: u8, key: &str) -> Feistel {
+        let size = alphabet
+            .base()
+            .checked_pow(len as u32)
+            .filter(|size| *size < 1 << 126)
+            .ok_or_else(|| anyhow!("{} characters are too many for counter ids", len))?;
+        ensure!(size >= 1 << 16, "counter ids need more characters");
+        let bits = 128 - (size - 1).leading_zeros();
+        Feistel {
+            alphabet,
+            len: len as usize,
+            size,
+            half_bits: bits.div_ceil(2),
+            key: key.as_bytes().to_vec(),
+        }
+    }
+
+    fn round(&self, round: u8, value: u128) -> u128 {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.key);
+        hasher.update([round]);
+        hasher.update(value.to_be_bytes());
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&hasher.finalize()[..16]);
+        u128::from_be_bytes(bytes) & self.mask()
+    }
+
+    fn mask(&self) -> u128 {
+        (1 << self.half_bits) - 1
+    }
+
+    fn permute(&self, value: u128) -> u128 {
+        let (mut left, mut right) = (value >> self.half_bits, value & self.mask());
+        for round in 0..FEISTEL_ROUNDS {
+            let next = left ^ self.round(round, right);
+            left = right;
+            right = next;
+        }
+        (left << self.half_bits) | right
+    }
+
+    fn unpermute(&self, value: u128) -> u128 {
+        let (mut left, mut right) = (value >> self.half_bits, value & self.mask());
+        for round in (0..FEISTEL_ROUNDS).rev() {
+            let previous = right ^ self.round(round, left);
+            right = left;
+            left = previous;
+        }
+        (left << self.half_bits) | right
+    }
+
+    /// The id for counter `n`, walking the cycle until it lands among the ids of `len` characters.
+    #[throws(anyhow::Error)]
+    pub fn encode(&self, n: u128) -> String {
+        ensure!(
+            n < self.size,
+            "ran out of {} character counter ids",
+            self.len
+        );
+        let mut value = self.permute(n);
+        while value >= self.size {
+            value = self.permute(value);
+        }
+        self.alphabet.encode(value, self.len)
+    }
+
+    /// The counter an id was made from.
+    pub fn decode(&self, id: &str) -> Option<u128> {
+        if id.len() != self.len {
+            return None;
+        }
+        let mut value = self.unpermute(self.alphabet.decode(id)?);
+        while value >= self.size {
+            value = self.unpermute(value);
+        }
+        Some(value)
+    }
+}
+
+pub const ID_COUNTER_KEY: &str = "id_counter";
+
+/// A counter shared through redis, obfuscated so that ids can not be enumerated.
+#[derive(Clone)]
+pub struct CounterIdGenerator {
+    redis_client: redis::Client,
+    feistel: Feistel,
+}
+
+impl CounterIdGenerator {
+    pub fn new(redis_client: redis::Client, feistel: Feistel) -> CounterIdGenerator {
+        CounterIdGenerator {
+            redis_client,
+            feistel,
+        }
+    }
+}
+
+#[async_trait]
+impl IdGenerator for CounterIdGenerator {
+    async fn gen_id(&self) -> anyhow::Result<String> {
+        let mut con = self.redis_client.get_async_connection().await?;
+        let n: u64 = redis::cmd("INCR")
+            .arg(ID_COUNTER_KEY)
+            .query_async(&mut con)
+            .await?;
+        self.feistel.encode(n as u128)
+    }
+
+    fn decode(&self, id: &str) -> Option<DecodedId> {
+        let counter = u64::try_from(self.feistel.decode(id)?).ok()?;
+        Some(DecodedId::Counter { counter })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn simple() {
-        let s1 = IdGenerator::new(7).gen_id();
+    #[async_std::test]
+    async fn simple() {
+        let s1 = TimeIdGenerator::new(7).gen_id().await.unwrap();
         println!("{}", s1);
         assert_eq!(s1.len(), 7);
-        let s2 = IdGenerator::new(8).gen_id();
+        let s2 = TimeIdGenerator::new(8).gen_id().await.unwrap();
         println!("{}", s2);
         assert_eq!(s2.len(), 8);
     }
@@ -119,9 +484,109 @@ mod tests {
         assert_eq!(id_from_ts_and_random(a, 8, dur, 2_u32.pow(12)), "baaw0SwG");
     }
 
+    #[test]
+    fn decode() {
+        let now = "2020-05-23T15:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let dur = now.signed_duration_since(*OUR_EPOCH).num_seconds() as u32;
+
+        let a = ALPHABET;
+        assert_eq!(ts_and_random_from_id(a, "aaaaaab"), Some((0, 1)));
+        assert_eq!(
+            ts_and_random_from_id(a, "a______"),
+            Some((*MAX_SECONDS - 1, 0))
+        );
+        assert_eq!(
+            ts_and_random_from_id(a, "Gaw0SwG"),
+            Some((dur, 2_u32.pow(11)))
+        );
+        // bits that did not fit are gone
+        assert_eq!(ts_and_random_from_id(a, "aaw0SwG"), Some((dur, 0)));
+        for rn in [0, 1, 12345, u32::MAX] {
+            let id = id_from_ts_and_random(a, 12, dur, rn);
+            assert_eq!(ts_and_random_from_id(a, &id), Some((dur, rn)));
+        }
+        // past 32 random bits there is only padding
+        assert_eq!(ts_and_random_from_id(a, "baaaaaaaaaaaaa"), None);
+        assert_eq!(ts_and_random_from_id(a, "aaaa"), None);
+        assert_eq!(ts_and_random_from_id(a, "aaaa+aa"), None);
+
+        let generator = TimeIdGenerator::new(8);
+        let id = id_from_ts_and_random(CRAZY_ALPHABET, 8, dur, 7);
+        assert_eq!(
+            generator.decode(&id),
+            Some(DecodedId::Time {
+                created_at: now,
+                seconds: dur,
+                random: 7,
+            })
+        );
+        // not made yet
+        let id = id_from_ts_and_random(CRAZY_ALPHABET, 8, *MAX_SECONDS - 1, 7);
+        assert_eq!(generator.decode(&id), None);
+        // not the configured length or alphabet, ie an alias
+        let id = id_from_ts_and_random(CRAZY_ALPHABET, 9, dur, 7);
+        assert_eq!(generator.decode(&id), None);
+        assert_eq!(generator.decode("sale+abc"), None);
+        assert_eq!(generator.decode("café"), None);
+    }
+
     #[test]
     #[should_panic(expected = "too small")]
     fn too_small() {
-        IdGenerator::new(6);
+        TimeIdGenerator::new(6);
+    }
+
+    #[test]
+    fn alphabets() {
+        assert!("abc".parse::<Alphabet>().is_ok());
+        assert!("a".parse::<Alphabet>().is_err());
+        assert!("aba".parse::<Alphabet>().is_err());
+        assert!("ab/".parse::<Alphabet>().is_err());
+        assert!("ab+".parse::<Alphabet>().is_err());
+
+        let time =
+            |alphabet: &str| id_generator(IdScheme::Time, 8, alphabet.parse().ok(), "", None);
+        assert!(time("abc").is_err());
+        assert!(time(std::str::from_utf8(ALPHABET).unwrap()).is_ok());
+        assert!(id_generator(IdScheme::Counter, 8, None, "redis://127.0.0.1/", None).is_err());
+    }
+
+    #[test]
+    fn random() {
+        let human = RandomIdGenerator {
+            alphabet: Alphabet(HUMAN_ALPHABET.to_vec()),
+            len: 10,
+        };
+        for _ in 0..100 {
+            let id = human.gen();
+            assert_eq!(id.len(), 10);
+            assert!(!id.contains(|c| "01ilo".contains(c)), "{}", id);
+        }
+    }
+
+    #[test]
+    fn counter() {
+        let feistel = Feistel::new(Alphabet(CRAZY_ALPHABET.to_vec()), 7, "secret").unwrap();
+        let ids: Vec<String> = (1..1000).map(|n| feistel.encode(n).unwrap()).collect();
+        for (n, id) in (1..1000).zip(&ids) {
+            assert_eq!(id.len(), 7);
+            assert_eq!(feistel.decode(id), Some(n));
+        }
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+        // neighbouring counters do not give neighbouring ids
+        assert_ne!(ids[0][..6], ids[1][..6]);
+
+        let other = Feistel::new(Alphabet(CRAZY_ALPHABET.to_vec()), 7, "other").unwrap();
+        assert_ne!(other.encode(1).unwrap(), ids[0]);
+
+        // sizes that are not a power of two walk the cycle
+        let human = Feistel::new(Alphabet(HUMAN_ALPHABET.to_vec()), 5, "secret").unwrap();
+        for n in 0..1000 {
+            assert_eq!(human.decode(&human.encode(n).unwrap()), Some(n));
+        }
+        assert!(human.encode(31_u128.pow(5)).is_err());
     }
 }
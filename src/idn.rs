@@ -0,0 +1,165 @@
+use std::collections::BTreeSet;
+
+use http_types::url::{Position, Url};
+
+/// Writing systems told apart when looking for hosts that imitate others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+    Other,
+}
+
+impl Script {
+    /// None for characters every script uses, like digits and `-`.
+    fn of(c: char) -> Option<Script> {
+        let script = match c {
+            _ if !c.is_alphabetic() => return None,
+            'a'..='z' | 'A'..='Z' | '\u{c0}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}' => Script::Latin,
+            '\u{370}'..='\u{3ff}' | '\u{1f00}'..='\u{1fff}' => Script::Greek,
+            '\u{400}'..='\u{52f}' | '\u{1c80}'..='\u{1c8f}' | '\u{2de0}'..='\u{2dff}' => {
+                Script::Cyrillic
+            }
+            '\u{a640}'..='\u{a69f}' => Script::Cyrillic,
+            '\u{530}'..='\u{58f}' => Script::Armenian,
+            '\u{590}'..='\u{5ff}' => Script::Hebrew,
+            '\u{600}'..='\u{6ff}' | '\u{750}'..='\u{77f}' => Script::Arabic,
+            '\u{900}'..='\u{97f}' => Script::Devanagari,
+            '\u{e00}'..='\u{e7f}' => Script::Thai,
+            '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => Script::Han,
+            '\u{3040}'..='\u{309f}' => Script::Hiragana,
+            '\u{30a0}'..='\u{30ff}' => Script::Katakana,
+            '\u{1100}'..='\u{11ff}' | '\u{ac00}'..='\u{d7af}' => Script::Hangul,
+            _ => Script::Other,
+        };
+        Some(script)
+    }
+
+    /// Chinese, Japanese and Korean are written mixing these, and with latin.
+    fn is_cjk(self) -> bool {
+        matches!(
+            self,
+            Script::Han | Script::Hiragana | Script::Katakana | Script::Hangul
+        )
+    }
+}
+
+/// Whether a host label is written in more than one script in a way real names are not.
+fn is_mixed_script(label: &str) -> bool {
+    let scripts: BTreeSet<Script> = label.chars().filter_map(Script::of).collect();
+    let other: Vec<&Script> = scripts.iter().filter(|s| !s.is_cjk()).collect();
+    match other.as_slice() {
+        [] => false,
+        [Script::Latin] => false,
+        [_] => scripts.len() > 1,
+        _ => true,
+    }
+}
+
+/// The url with an ascii host and path, as a `Location` header needs it. Left as is when it is
+/// ascii already or does not parse.
+pub fn ascii_url(url: &str) -> String {
+    if url.is_ascii() {
+        return url.to_owned();
+    }
+    match Url::parse(url) {
+        Ok(u) => u.to_string(),
+        Err(_) => url.to_owned(),
+    }
+}
+
+/// The host of `url` for people to read, punycode turned back into unicode.
+pub fn unicode_host(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    Some(idna::domain_to_unicode(host).0)
+}
+
+/// The url for people to read, with a unicode host and the rest as it was given.
+pub fn unicode_url(url: &str) -> String {
+    let parsed = match Url::parse(url) {
+        Ok(u) => u,
+        Err(_) => return url.to_owned(),
+    };
+    match parsed.host_str() {
+        Some(host) if host.split('.').any(|l| l.starts_with("xn--")) => {
+            let (unicode, result) = idna::domain_to_unicode(host);
+            if result.is_err() {
+                return url.to_owned();
+            }
+            format!(
+                "{}{}{}",
+                &parsed[..Position::BeforeHost],
+                unicode,
+                &parsed[Position::AfterHost..]
+            )
+        }
+        _ => url.to_owned(),
+    }
+}
+
+/// A warning when the host of `url` mixes scripts, like a cyrillic `а` in `pаypal.com`.
+pub fn homograph_warning(url: &str) -> Option<String> {
+    let host = unicode_host(url)?;
+    if host.split('.').any(is_mixed_script) {
+        Some(format!(
+            "the host [{}] mixes scripts and may imitate another domain",
+            host
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts() {
+        assert_eq!(
+            ascii_url("https://bücher.example/straße?q=ü"),
+            "https://xn--bcher-kva.example/stra%C3%9Fe?q=%C3%BC"
+        );
+        assert_eq!(ascii_url("https://example.com"), "https://example.com");
+        assert_eq!(ascii_url("not a url ü"), "not a url ü");
+
+        assert_eq!(
+            unicode_url("https://xn--bcher-kva.example/a?b=c"),
+            "https://bücher.example/a?b=c"
+        );
+        assert_eq!(
+            unicode_url("https://bücher.example/a"),
+            "https://bücher.example/a"
+        );
+        assert_eq!(unicode_url("https://example.com"), "https://example.com");
+        assert_eq!(
+            unicode_host("https://xn--bcher-kva.example:8080/"),
+            Some("bücher.example".to_owned())
+        );
+    }
+
+    #[test]
+    fn homographs() {
+        // a cyrillic а
+        assert!(homograph_warning("https://p\u{430}ypal.com/login").is_some());
+        assert!(homograph_warning("https://xn--pypal-4ve.com/").is_some());
+        assert!(homograph_warning("https://paypal.com/").is_none());
+        assert!(homograph_warning("https://bücher.example/").is_none());
+        assert!(homograph_warning("https://пример.рф/").is_none());
+        assert!(homograph_warning("https://例え.jp/").is_none());
+        assert!(homograph_warning("https://sonyの.jp/").is_none());
+        assert!(homograph_warning("https://пример例.com/").is_some());
+        assert!(homograph_warning("https://παypal.com/").is_some());
+    }
+}
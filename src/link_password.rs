@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use fehler::*;
+use hmac::{Hmac, Mac, NewMac};
+use rand::{thread_rng, Rng};
+use sha2::Sha256;
+
+pub const ACCESS_COOKIE_NAME: &str = "_utrakr_pw";
+
+#[throws(anyhow::Error)]
+pub fn hash_password(password: &str) -> String {
+    bcrypt::hash(password, bcrypt::DEFAULT_COST)?
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match bcrypt::verify(password, hash) {
+        Ok(valid) => valid,
+        Err(e) => {
+            warn!("unable to verify password hash, {}", e);
+            false
+        }
+    }
+}
+
+/// Signs the short lived cookies that let a visitor through a password protected micro url.
+#[derive(Clone)]
+pub struct AccessSigner {
+    key: Arc<Vec<u8>>,
+    ttl: Duration,
+}
+
+impl AccessSigner {
+    pub fn new(key: Option<&str>, ttl: Duration) -> AccessSigner {
+        let key = match key {
+            Some(k) => k.as_bytes().to_vec(),
+            None => {
+                warn!(
+                    "no cookie signing key configured, access cookies will not survive a restart"
+                );
+                thread_rng().gen::<[u8; 32]>().to_vec()
+            }
+        };
+        AccessSigner {
+            key: Arc::new(key),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn mac(&self, id: &str, expires: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.key).expect("hmac accepts keys of any size");
+        mac.update(format!("{}.{}", id, expires).as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, id: &str, now: DateTime<Utc>) -> String {
+        let expires = (now + self.ttl).timestamp();
+        let sig = self.mac(id, expires).finalize().into_bytes();
+        format!("{}.{}", expires, hex::encode(sig))
+    }
+
+    pub fn verify(&self, id: &str, value: &str, now: DateTime<Utc>) -> bool {
+        let mut parts = value.splitn(2, '.');
+        let expires = parts.next().and_then(|e| e.parse::<i64>().ok());
+        let sig = parts.next().and_then(|s| hex::decode(s).ok());
+        match (expires, sig) {
+            (Some(expires), Some(sig)) if expires > now.timestamp() => {
+                self.mac(id, expires).verify(&sig).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// failure count and when the window started
+type Attempts = HashMap<String, (u32, DateTime<Utc>)>;
+
+/// Counts failed password attempts per key within a window.
+#[derive(Clone)]
+pub struct FailedAttempts {
+    max_attempts: u32,
+    window: Duration,
+    attempts: Arc<Mutex<Attempts>>,
+}
+
+impl FailedAttempts {
+    pub fn new(max_attempts: u32, window: Duration) -> FailedAttempts {
+        FailedAttempts {
+            max_attempts,
+            window,
+            attempts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_blocked(&self, key: &str, now: DateTime<Utc>) -> bool {
+        let attempts = self.attempts.lock().unwrap();
+        match attempts.get(key) {
+            Some((count, started)) => *started + self.window > now && *count >= self.max_attempts,
+            None => false,
+        }
+    }
+
+    pub fn record_failure(&self, key: &str, now: DateTime<Utc>) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let window = self.window;
+        attempts.retain(|_, (_, started)| *started + window > now);
+        let entry = attempts.entry(key.to_owned()).or_insert((0, now));
+        entry.0 += 1;
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.attempts.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password() {
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not a hash"));
+    }
+
+    #[test]
+    fn access_cookie() {
+        let now = Utc::now();
+        let signer = AccessSigner::new(Some("secret"), Duration::minutes(10));
+        let value = signer.sign("abcdefgh", now);
+        assert!(signer.verify("abcdefgh", &value, now));
+        assert!(!signer.verify("abcdefgi", &value, now));
+        assert!(!signer.verify("abcdefgh", &value, now + Duration::minutes(11)));
+        assert!(!signer.verify("abcdefgh", "123.abc", now));
+        assert!(!signer.verify("abcdefgh", "", now));
+
+        let other = AccessSigner::new(Some("other"), Duration::minutes(10));
+        assert!(!other.verify("abcdefgh", &value, now));
+    }
+
+    #[test]
+    fn attempts() {
+        let now = Utc::now();
+        let attempts = FailedAttempts::new(3, Duration::minutes(5));
+        for _ in 0..3 {
+            assert!(!attempts.is_blocked("k", now));
+            attempts.record_failure("k", now);
+        }
+        assert!(attempts.is_blocked("k", now));
+        assert!(!attempts.is_blocked("other", now));
+        assert!(!attempts.is_blocked("k", now + Duration::minutes(6)));
+
+        attempts.reset("k");
+        assert!(!attempts.is_blocked("k", now));
+    }
+}
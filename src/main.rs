@@ -1,7 +1,10 @@
 #[macro_use]
 extern crate log;
 
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use async_std::sync::{Arc, Mutex};
 use fehler::*;
@@ -11,45 +14,250 @@ use structopt::StructOpt;
 use tide::http::Cookie;
 use tide::log::LevelFilter;
 use tide::security::{CorsMiddleware, Origin};
-use tide::{Body, Redirect, Request, Response, StatusCode};
+use tide::utils::async_trait;
+use tide::{Body, Middleware, Next, Redirect, Request, Response, StatusCode};
 use time::{Duration, OffsetDateTime};
 
-use crate::dao::url_dao::{MicroUrlInfo, UrlDao};
+use crate::chain::{resolve_chain, ShortenerFollower};
+use crate::dao::api_key_dao::{ApiKey, ApiKeyDao, Scope, API_KEY_PREFIX};
+use crate::dao::health_dao::{HealthDao, LinkHealth};
+use crate::dao::idempotency_dao::{request_hash, Idempotency, IdempotencyDao};
+use crate::dao::metadata_dao::MetadataDao;
+use crate::dao::url_dao::{
+    AppLink, Destination, DestinationVersion, MicroUrlData, MicroUrlInfo, ScheduledDestination,
+    SocialCard, UrlDao,
+};
+use crate::dao::workspace_dao::{Role, Workspace, WorkspaceDao};
+use crate::data::link_search::LinkSearchRequest;
 use crate::data::views::{ViewsDao, ViewsData, ViewsRequest};
 use crate::events::event_logger::EventLogger;
 use crate::events::ulid::UlidGenerator;
-use crate::google_auth::{get_claim_from_google, GoogleClaims};
+use crate::google_auth::{google_claims, GoogleClaims};
+use crate::health::HealthChecker;
+use crate::id_generator::{Alphabet, DecodedId, IdScheme};
+use crate::idn::{ascii_url, homograph_warning, unicode_url};
+use crate::link_password::{
+    hash_password, verify_password, AccessSigner, FailedAttempts, ACCESS_COOKIE_NAME,
+};
+use crate::metadata::{MetadataFetcher, PageMetadata};
+use crate::platform::{is_link_preview_bot, Platform};
+use crate::qr::{QrLogo, QrOptions, QrRequest};
+use crate::rate_limit::{Client, Limit, RateLimit, RateLimitStore};
+use crate::split::choose_variant;
+use crate::url_policy::UrlPolicyFile;
+use crate::utils::{client_ip, encode_id, is_valid_alias, normalize_id, trim_id};
 
+mod chain;
 mod dao;
 mod data;
 mod events;
+#[cfg(test)]
+mod fake_redis;
 mod google_auth;
+mod health;
+mod id_filter;
 mod id_generator;
+mod idn;
+mod link_password;
+mod metadata;
+mod pages;
+mod platform;
+mod qr;
+mod rate_limit;
+mod split;
+mod url_policy;
 mod utils;
 
 const LOG_HEADERS: [&str; 2] = ["user-agent", "referer"];
 const COOKIE_NAME: &str = "_utrakr";
 const APP_NAME: &str = "utrakr-api";
+/// how many micro urls close to a missing id are suggested
+const MAX_SUGGESTIONS: usize = 3;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct ShortenResponse {
     data: MicroUrlInfo,
     request: ShortenRequest,
     google_auth: Option<GoogleClaims>,
+    /// an earlier micro url of the owner for the same long url, nothing was created
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    existing: bool,
+    /// ie destination hosts that may imitate another domain
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct ShortenRequest {
     long_url: String,
     id_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    destinations: Option<Vec<Destination>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    app_link: Option<AppLink>,
+    /// never echoed back or logged
+    #[serde(default, skip_serializing)]
+    password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    not_before: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<Vec<ScheduledDestination>>,
+    /// one of the extra base hosts, the default host when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    /// needs an `id_token` for an editor or owner of the workspace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    workspace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    social: Option<SocialCard>,
+    /// get back the owner's existing micro url for the same long url, defaults to the server's
+    /// `dedupe_by_default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dedupe: Option<bool>,
+    /// a vanity id instead of a generated one, letters of any script, digits, emoji, `-` and `_`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+}
+
+impl ShortenRequest {
+    fn is_valid(&self, url_dao: &UrlDao) -> bool {
+        let destinations_valid = match self.destinations {
+            Some(ref d) => d.iter().any(|d| d.weight > 0),
+            None => true,
+        };
+        let domain_valid = match self.domain {
+            Some(ref d) => url_dao.has_domain(d),
+            None => true,
+        };
+        let alias_valid = match self.alias {
+            Some(ref a) => is_valid_alias(&normalize_id(a)),
+            None => true,
+        };
+        let app_link_valid = self.app_link.as_ref().is_none_or(|a| a.is_valid());
+        destinations_valid && domain_valid && alias_valid && app_link_valid
+    }
+
+    fn long_urls_mut(&mut self) -> Vec<&mut String> {
+        let mut long_urls = vec![&mut self.long_url];
+        for d in self.destinations.iter_mut().flatten() {
+            long_urls.push(&mut d.long_url);
+        }
+        for s in self.schedule.iter_mut().flatten() {
+            long_urls.push(&mut s.long_url);
+        }
+        long_urls
+    }
+
+    /// Every destination the micro url could send visitors to.
+    fn long_urls(&self) -> Vec<&str> {
+        let mut long_urls = vec![self.long_url.as_str()];
+        for d in self.destinations.iter().flatten() {
+            long_urls.push(&d.long_url);
+        }
+        for s in self.schedule.iter().flatten() {
+            long_urls.push(&s.long_url);
+        }
+        long_urls
+    }
+
+    /// The destinations and the app link's urls, everything the url policy has to allow.
+    fn policy_urls(&self) -> Vec<&str> {
+        let mut urls = self.long_urls();
+        urls.extend(self.app_link.iter().flat_map(|a| a.urls()));
+        urls
+    }
+
+    #[throws(anyhow::Error)]
+    async fn to_micro_url_data(&self, owner: Option<String>) -> MicroUrlData {
+        let password_hash = match self.password {
+            Some(ref p) => {
+                let p = p.to_owned();
+                Some(async_std::task::spawn_blocking(move || hash_password(&p)).await?)
+            }
+            None => None,
+        };
+        let mut schedule = self.schedule.clone().unwrap_or_default();
+        schedule.sort_by_key(|s| s.from);
+        let versions = vec![DestinationVersion {
+            version: 1,
+            long_url: self.long_url.to_owned(),
+            changed_by: owner.to_owned(),
+            changed_at: Some(chrono::Utc::now()),
+        }];
+        let mut data = MicroUrlData {
+            destinations: self.destinations.clone().unwrap_or_default(),
+            app_link: self.app_link.clone(),
+            password_hash,
+            owner,
+            not_before: self.not_before,
+            schedule,
+            versions,
+            domain: self.domain.as_ref().map(|d| d.to_ascii_lowercase()),
+            workspace: self.workspace.to_owned(),
+            title: self.title.to_owned(),
+            notes: self.notes.to_owned(),
+            social: self.social.clone().filter(|s| !s.is_empty()),
+            ..MicroUrlData::new(&self.long_url)
+        };
+        data.set_tags(&self.tags);
+        data
+    }
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct RedirectEvent {
+    id: String,
+    /// of the destination history, none when a variant or a scheduled destination was served
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// the schedule entry that was served
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_from: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<Platform>,
     cookie: Option<RedirectCookieInfo>,
     headers: MultiMap<String, String>,
 }
 
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct ConversionEvent {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<usize>,
+    cookie: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct PasswordEvent {
+    id: String,
+    outcome: &'static str,
+    ip: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct BlockEvent<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a str>,
+    url: &'a str,
+    rule: String,
+    /// create, change or redirect
+    stage: &'static str,
+    ip: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PasswordForm {
+    password: String,
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct RedirectCookieInfo {
     value: String,
@@ -60,8 +268,13 @@ struct RedirectCookieInfo {
 }
 
 impl RedirectEvent {
-    fn empty() -> RedirectEvent {
+    fn new(id: &str) -> RedirectEvent {
         RedirectEvent {
+            id: id.to_owned(),
+            version: None,
+            scheduled_from: None,
+            variant: None,
+            platform: None,
             cookie: None,
             headers: MultiMap::new(),
         }
@@ -102,75 +315,451 @@ struct AppConfig {
     redirect_homepage: String,
     #[structopt(env, default_value = "localhost:8080")]
     default_base_host: String,
+    /// more branded hosts micro urls can be created on, comma separated
+    #[structopt(long, env, use_delimiter = true)]
+    extra_base_hosts: Vec<String>,
+    /// the oauth client id google id tokens must be issued to, google logins are refused without
+    #[structopt(long, env)]
+    google_client_id: Option<String>,
+    /// google emails that may use the `/private` routes, comma separated
+    #[structopt(long, env, use_delimiter = true)]
+    admin_emails: Vec<String>,
     #[structopt(env, parse(try_from_str), default_value = "false")]
     cookie_secure: bool,
     #[structopt(env, default_value = "redis://127.0.0.1/")]
     redis_urls_client_conn: String,
     #[structopt(env, parse(try_from_str), default_value = "/tmp/utrakr-api")]
     event_log_folder: PathBuf,
+    #[structopt(env)]
+    cookie_signing_key: Option<Secret>,
+    #[structopt(env, default_value = "30")]
+    password_access_minutes: i64,
+    #[structopt(env, default_value = "5")]
+    password_max_attempts: u32,
+    /// failed password attempts on a link from anywhere before every attempt on it waits
+    /// `password_link_delay_ms` for the rest of the window, for guesses spread over many ips
+    #[structopt(env, default_value = "50")]
+    password_max_attempts_per_link: u32,
+    #[structopt(env, default_value = "2000")]
+    password_link_delay_ms: u64,
+    #[structopt(env, default_value = "15")]
+    password_attempts_window_minutes: i64,
+    /// proxies in front of the app that append to `x-forwarded-for`, 0 uses the peer address
+    #[structopt(env, default_value = "1")]
+    trusted_proxy_hops: usize,
+    /// png placed in the middle of qr codes that ask for a logo
+    #[structopt(env, parse(from_os_str))]
+    qr_logo_path: Option<PathBuf>,
+    /// allow and deny rules for destinations, see `UrlPolicy::parse`
+    #[structopt(env, parse(from_os_str))]
+    url_policy_path: Option<PathBuf>,
+    /// how often the url policy file is checked for changes
+    #[structopt(env, default_value = "30")]
+    url_policy_reload_seconds: u64,
+    /// `<burst>:<per minute>` for creating micro urls, `0:0` turns it off
+    #[structopt(env, default_value = "20:10")]
+    rate_limit_create: Limit,
+    /// `<burst>:<per minute>` for following micro urls, their previews and qr codes
+    #[structopt(env, default_value = "300:600")]
+    rate_limit_redirect: Limit,
+    /// `<burst>:<per minute>` for everything under `/api`
+    #[structopt(env, default_value = "60:60")]
+    rate_limit_api: Limit,
+    /// `<burst>:<per minute>` for suggesting micro urls on a miss, a plain 404 past it
+    #[structopt(env, default_value = "10:10")]
+    rate_limit_suggest: Limit,
+    /// share the rate limits between instances through redis rather than per process
+    #[structopt(env, parse(try_from_str), default_value = "false")]
+    rate_limit_redis: bool,
+    /// how often healthy destinations are checked, failing ones back off from this, 0 turns it off.
+    /// Micro urls created before the checks were added are not checked
+    #[structopt(env, default_value = "60")]
+    health_check_minutes: i64,
+    #[structopt(env, default_value = "8")]
+    health_check_concurrency: usize,
+    #[structopt(env, default_value = "10")]
+    health_check_timeout_seconds: u64,
+    #[structopt(env, default_value = "5")]
+    metadata_timeout_seconds: u64,
+    /// only the start of destination pages is read for their metadata
+    #[structopt(env, default_value = "256")]
+    metadata_max_kb: u64,
+    /// lets metadata be scraped, shorteners be followed and health be checked on private
+    /// addresses, for local development only
+    #[structopt(env, parse(try_from_str), default_value = "false")]
+    metadata_allow_private_ips: bool,
+    /// how long an `idempotency-key` of a create is remembered
+    #[structopt(env, default_value = "24")]
+    idempotency_ttl_hours: usize,
+    /// whether creating a micro url for a long url the owner already has one for returns that one,
+    /// when the request does not say
+    #[structopt(env, parse(try_from_str), default_value = "false")]
+    dedupe_by_default: bool,
+    /// how ids are made, `time`, `random`, `counter` or `human`
+    #[structopt(env, default_value = "time")]
+    id_scheme: IdScheme,
+    #[structopt(env, default_value = "8")]
+    id_length: u8,
+    /// characters of ids, each once, the scheme picks when not set, `time` needs exactly 64
+    #[structopt(env)]
+    id_alphabet: Option<Alphabet>,
+    /// key that keeps `counter` ids from being enumerated, required by that scheme
+    #[structopt(env)]
+    id_counter_secret: Option<Secret>,
+    /// words new ids must not spell, also as leetspeak, one per line, a built in list when not set
+    #[structopt(env, parse(from_os_str))]
+    id_blocked_words_path: Option<PathBuf>,
+    /// avoid ids with characters that are easy to mix up when printed, like `l1I0O`, not with
+    /// the `time` scheme whose alphabet has all of them
+    #[structopt(env, parse(try_from_str), default_value = "false")]
+    id_exclude_ambiguous: bool,
+    /// other url shorteners whose redirects are followed for new micro urls, comma separated
+    #[structopt(
+        long,
+        env,
+        use_delimiter = true,
+        default_value = "bit.ly,buff.ly,cutt.ly,goo.gl,is.gd,ow.ly,rebrand.ly,t.co,tinyurl.com"
+    )]
+    follow_shortener_hosts: Vec<String>,
+    /// how many of their redirects are followed, 0 keeps their urls as given
+    #[structopt(env, default_value = "0")]
+    follow_shortener_hops: usize,
+}
+
+/// Config value that is kept out of the logs.
+#[derive(Clone)]
+struct Secret(String);
+
+impl FromStr for Secret {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Secret(s.to_owned()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
 }
 
 #[derive(Clone)]
 struct AppState {
     app_config: AppConfig,
     url_dao: UrlDao,
+    workspace_dao: WorkspaceDao,
+    api_key_dao: ApiKeyDao,
+    health_dao: HealthDao,
+    metadata_dao: MetadataDao,
+    metadata_fetcher: MetadataFetcher,
+    /// scheduled links already scraped once they went live, see `redirect_micro_url`
+    scraped_when_active: Arc<std::sync::Mutex<HashSet<String>>>,
+    shortener_follower: ShortenerFollower,
+    idempotency_dao: IdempotencyDao,
     views_dao: ViewsDao,
     event_logger: EventLogger,
     ulid_generator: Arc<Mutex<UlidGenerator>>,
+    access_signer: AccessSigner,
+    failed_attempts: FailedAttempts,
+    link_failed_attempts: FailedAttempts,
+    qr_logo: Option<Arc<QrLogo>>,
+    url_policy: UrlPolicyFile,
+    suggest_limit: RateLimit,
 }
 
 async fn create_micro_url(mut req: Request<AppState>) -> tide::Result<Response> {
-    if let Ok(request) = req.body_json::<ShortenRequest>().await {
+    let body = req.body_string().await.unwrap_or_default();
+    if let Ok(request) = serde_json::from_str::<ShortenRequest>(&body) {
         let url_dao = &req.state().url_dao;
-        let google_auth = if let Some(ref tk) = request.id_token {
-            get_claim_from_google(tk)
-        } else {
-            None
+        if !request.is_valid(url_dao) {
+            return Ok(Response::new(StatusCode::UnprocessableEntity));
+        }
+        let google_auth = match request.id_token {
+            Some(ref tk) => {
+                google_claims(tk, req.state().app_config.google_client_id.as_deref()).await
+            }
+            None => None,
         };
+        // an id token in the body, or a bearer token allowed to write links
+        let account = match google_auth {
+            Some(ref c) => Some(UserAccount::new(&c.email)),
+            None => read_auth(&req, Scope::LinksWrite),
+        };
+        // a retry with the same key gets the first response, without creating anything again
+        let idempotency = req.header("idempotency-key").map(|key| {
+            let scope = match account {
+                Some(ref a) => format!("email:{}", a.email),
+                None => format!(
+                    "ip:{}",
+                    client_ip(&req, req.state().app_config.trusted_proxy_hops).unwrap_or_default()
+                ),
+            };
+            (scope, key.as_str().to_owned(), request_hash(&body))
+        });
+        if let Some((ref scope, ref key, ref hash)) = idempotency {
+            let begun = req
+                .state()
+                .idempotency_dao
+                .begin(scope, key, hash)
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            match begun {
+                Idempotency::New => (),
+                Idempotency::Done(response) => return Ok(json_response(response)),
+                Idempotency::Pending => return Ok(Response::new(StatusCode::Conflict)),
+                Idempotency::Mismatch => return Ok(Response::new(StatusCode::UnprocessableEntity)),
+            }
+        }
+
+        let response =
+            create_claimed(&req, request, account, google_auth, idempotency.as_ref()).await;
+        // a retry after a failure is handled again rather than told the first is still going
+        if let Some((ref scope, ref key, ref hash)) = idempotency {
+            if !response.as_ref().is_ok_and(|r| r.status().is_success()) {
+                req.state()
+                    .idempotency_dao
+                    .release(scope, key, hash)
+                    .await
+                    .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            }
+        }
+        response
+    } else {
+        Ok(Response::new(StatusCode::UnprocessableEntity))
+    }
+}
 
-        let data = url_dao
-            .create_micro_url(&request.long_url)
+/// Creates the micro url once the idempotency key, if any, is claimed.
+async fn create_claimed(
+    req: &Request<AppState>,
+    mut request: ShortenRequest,
+    account: Option<UserAccount>,
+    google_auth: Option<GoogleClaims>,
+    idempotency: Option<&(String, String, String)>,
+) -> tide::Result<Response> {
+    let url_dao = &req.state().url_dao;
+    // no chains of redirects through us or other shorteners, the policy checks the end
+    let mut warnings = vec![];
+    for long_url in request.long_urls_mut() {
+        let resolved = resolve_chain(url_dao, &req.state().shortener_follower, long_url, None)
             .await
             .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
-
-        let response = ShortenResponse {
-            data,
-            request,
-            google_auth,
+        match resolved {
+            Ok(resolved) if resolved != *long_url => {
+                warnings.push(format!("[{}] redirects to [{}]", long_url, resolved));
+                *long_url = resolved;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                return Ok(Response::builder(StatusCode::UnprocessableEntity)
+                    .body(e.to_string())
+                    .build())
+            }
+        }
+    }
+    if blocked_url(req, None, &request.policy_urls(), "create")
+        .await
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?
+    {
+        return Ok(Response::new(StatusCode::Forbidden));
+    }
+    if let Some(ref workspace) = request.workspace {
+        let role = match account {
+            Some(ref account) => workspace_role(req, account, workspace)
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?,
+            None => None,
         };
-        let event_logger = &req.state().event_logger;
-        event_logger
-            .log_event("create", &response)
+        if role < Some(Role::Editor) {
+            return Ok(Response::new(StatusCode::Forbidden));
+        }
+    }
+
+    let alias = request.alias.as_deref().map(normalize_id);
+    let micro_url_data = request
+        .to_micro_url_data(account.map(|a| a.email))
+        .await
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+    let dedupe = request
+        .dedupe
+        .unwrap_or(req.state().app_config.dedupe_by_default);
+    let existing = match micro_url_data.owner {
+        Some(ref owner) if dedupe && alias.is_none() && micro_url_data.is_plain() => url_dao
+            .find_owned_url(owner, &micro_url_data.long_url)
             .await
-            .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?
+            .filter(|(_, d)| {
+                d.is_plain()
+                    && d.domain == micro_url_data.domain
+                    && d.workspace == micro_url_data.workspace
+            }),
+        _ => None,
+    };
+    let (data, existing) = match existing {
+        // the details asked for are not silently dropped
+        Some((id, d)) if !has_details_of(&d, &micro_url_data) => {
+            return Ok(Response::builder(StatusCode::Conflict)
+                .body(format!("[{}] already links there with other details", id))
+                .build())
+        }
+        Some((id, d)) => (url_dao.micro_url_info(&id, &d), true),
+        None => {
+            let data = url_dao
+                .create_micro_url(&micro_url_data, alias.as_deref())
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            let data = match data {
+                Some(data) => data,
+                None => return Ok(Response::new(StatusCode::Conflict)),
+            };
+            if let Some(ref workspace) = micro_url_data.workspace {
+                req.state()
+                    .workspace_dao
+                    .add_link(workspace, &data.id)
+                    .await
+                    .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            }
+            fetch_metadata(req.state(), &data.id, &micro_url_data);
+            (data, false)
+        }
+    };
 
-        Ok(Response::builder(StatusCode::Ok)
-            .body(Body::from_json(&response)?)
-            .build())
+    warnings.extend(
+        request
+            .long_urls()
+            .into_iter()
+            .filter_map(homograph_warning),
+    );
+    let response = ShortenResponse {
+        data,
+        request,
+        google_auth,
+        existing,
+        warnings,
+    };
+    let event_logger = &req.state().event_logger;
+    let event = if response.existing {
+        "existing"
     } else {
-        Ok(Response::new(StatusCode::UnprocessableEntity))
+        "create"
+    };
+    event_logger
+        .log_event(event, &response)
+        .await
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+
+    let response = serde_json::to_string(&response)?;
+    if let Some((scope, key, hash)) = idempotency {
+        req.state()
+            .idempotency_dao
+            .finish(scope, key, hash, &response)
+            .await
+            .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
     }
+    Ok(json_response(response))
+}
+
+/// Whether `existing` has the title, notes, tags and social card `requested` has, if any.
+fn has_details_of(existing: &MicroUrlData, requested: &MicroUrlData) -> bool {
+    (requested.title.is_none() || requested.title == existing.title)
+        && (requested.notes.is_none() || requested.notes == existing.notes)
+        && (requested.tags.is_empty() || requested.tags == existing.tags)
+        && (requested.social.is_none() || requested.social == existing.social)
+}
+
+/// The `:id` of the route as it is stored.
+fn id_param(req: &Request<AppState>) -> String {
+    normalize_id(req.param("id").unwrap_or(""))
+}
+
+fn json_response(body: String) -> Response {
+    Response::builder(StatusCode::Ok)
+        .body(body)
+        .content_type(tide::http::mime::JSON)
+        .build()
 }
 
 async fn redirect_micro_url(req: Request<AppState>) -> tide::Result<Response> {
-    let id: &str = req.param("id").unwrap_or("");
+    let param: &str = &id_param(&req);
+    // ids copied from text often come with the punctuation after them
+    let trimmed = trim_id(param);
+    if let Some(id) = param
+        .strip_suffix('+')
+        .or_else(|| trimmed.strip_suffix('+'))
+    {
+        return preview_micro_url(&req, id).await;
+    }
     let url_dao = &req.state().url_dao;
-    let domain: String = req.state().app_config.default_base_host.to_owned();
     let cookie_secure = req.state().app_config.cookie_secure;
 
-    let found: Option<String> = url_dao
+    let mut id = param;
+    let mut found: Option<MicroUrlData> = url_dao
         .get_micro_url(id)
         .await
         .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
-    match found {
-        Some(long_url) => {
-            let mut response: Response = Redirect::temporary(long_url).into();
-            let mut event = RedirectEvent::empty();
+    if found.is_none() && trimmed != param {
+        id = trimmed;
+        found = url_dao
+            .get_micro_url(id)
+            .await
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    }
+    match found.filter(|data| served_on_request_host(&req, data)) {
+        Some(data) => {
+            let domain: String = data
+                .domain
+                .to_owned()
+                .unwrap_or_else(|| req.state().app_config.default_base_host.to_owned());
+            let now = chrono::Utc::now();
+            if !data.is_active(now) {
+                return Ok(pages::html_response(
+                    StatusCode::Ok,
+                    pages::coming_soon(data.not_before),
+                ));
+            }
+            if data.password_hash.is_some() {
+                let unlocked = req
+                    .cookie(ACCESS_COOKIE_NAME)
+                    .is_some_and(|c| req.state().access_signer.verify(id, c.value(), now));
+                if !unlocked {
+                    return Ok(pages::html_response(
+                        StatusCode::Ok,
+                        pages::password_form(id, false),
+                    ));
+                }
+            }
+            // scheduled links were not scraped when they were made
+            if data.not_before.is_some()
+                && req
+                    .state()
+                    .scraped_when_active
+                    .lock()
+                    .unwrap()
+                    .insert(id.to_owned())
+            {
+                let scraped = req
+                    .state()
+                    .metadata_dao
+                    .get_metadata(id)
+                    .await
+                    .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+                if scraped.is_none() {
+                    fetch_metadata(req.state(), id, &data);
+                }
+            }
+            let user_agent = req.header("user-agent").map(|v| v.as_str()).unwrap_or("");
+            if is_link_preview_bot(user_agent) {
+                return unfurl_micro_url(&req, id, &data).await;
+            }
+
+            let mut event = RedirectEvent::new(id);
 
             // build or save cookie
-            if let Some(c) = req.cookie(COOKIE_NAME) {
+            let new_cookie = if let Some(c) = req.cookie(COOKIE_NAME) {
                 debug!("cookie: {}", c);
                 event.set_from_cookie(&c);
+                None
             } else {
                 let mut gen = req.state().ulid_generator.lock().await;
                 let mut now = OffsetDateTime::now_utc();
@@ -181,19 +770,64 @@ async fn redirect_micro_url(req: Request<AppState>) -> tide::Result<Response> {
                     .secure(cookie_secure);
 
                 let cookie = (if !domain.starts_with("localhost") {
-                    cookie_builder.domain(domain)
+                    cookie_builder.domain(strip_port(&domain).to_owned())
                 } else {
                     cookie_builder
                 })
                 .finish();
                 event.set_from_cookie(&cookie);
-                response.insert_cookie(cookie);
+                Some(cookie)
+            };
+
+            // pick the destination, sticky per visitor
+            let visitor = event
+                .cookie
+                .as_ref()
+                .map(|c| c.value.as_str())
+                .unwrap_or("");
+            event.variant = choose_variant(visitor, id, &data.destinations);
+            let long_url = match (event.variant, data.scheduled_at(now)) {
+                (Some(v), _) => data.destinations[v].long_url.to_owned(),
+                (None, Some(scheduled)) => {
+                    event.scheduled_from = Some(scheduled.from);
+                    scheduled.long_url.to_owned()
+                }
+                (None, None) => {
+                    event.version = Some(data.current_version());
+                    data.long_url.to_owned()
+                }
+            };
+            // app links stored before their urls were checked fall back to the destination
+            let app_link = data.app_link.as_ref().filter(|a| a.is_valid());
+            let mut served_urls = vec![long_url.as_str()];
+            served_urls.extend(app_link.iter().flat_map(|a| a.urls()));
+            if blocked_url(&req, Some(id), &served_urls, "redirect")
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?
+            {
+                return Ok(pages::html_response(
+                    StatusCode::Gone,
+                    pages::link_disabled(),
+                ));
+            }
+            // headers are ascii, idn hosts go as punycode
+            let long_url = ascii_url(&long_url);
+            let mut response: Response = match app_link {
+                Some(app_link) => {
+                    let platform = Platform::from_user_agent(user_agent);
+                    event.platform = Some(platform);
+                    app_link_response(app_link, platform, long_url)
+                }
+                None => Redirect::temporary(long_url).into(),
             };
+            if let Some(cookie) = new_cookie {
+                response.insert_cookie(cookie);
+            }
 
             // read headers
             for header in LOG_HEADERS.iter() {
                 if let Some(values) = req.header(*header) {
-                    event.add_header_values(*header, values);
+                    event.add_header_values(header, values);
                 }
             }
 
@@ -205,16 +839,423 @@ async fn redirect_micro_url(req: Request<AppState>) -> tide::Result<Response> {
 
             Ok(response)
         }
+        None => not_found(&req, id).await,
+    }
+}
+
+/// A 404 that lists the micro urls on this host that are a typo away from `id`. Only ones anybody
+/// could follow are suggested, and only while the caller is within the suggestion rate limit.
+async fn not_found(req: &Request<AppState>, id: &str) -> tide::Result<Response> {
+    if id.is_empty() || !req.state().suggest_limit.allows(req).await {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    let url_dao = &req.state().url_dao;
+    let now = chrono::Utc::now();
+    let micro_urls: Vec<String> = url_dao
+        .near_micro_urls(id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into_iter()
+        .filter(|(_, data)| {
+            served_on_request_host(req, data) && data.is_active(now) && data.password_hash.is_none()
+        })
+        .map(|(near_id, data)| url_dao.micro_url_info(&near_id, &data).micro_url)
+        .take(MAX_SUGGESTIONS)
+        .collect();
+    if micro_urls.is_empty() {
+        return Ok(Response::new(StatusCode::NotFound));
+    }
+    Ok(pages::html_response(
+        StatusCode::NotFound,
+        pages::did_you_mean(&micro_urls),
+    ))
+}
+
+async fn preview(req: Request<AppState>) -> tide::Result<Response> {
+    let id: &str = &id_param(&req);
+    preview_micro_url(&req, id).await
+}
+
+/// The card link preview bots show for a micro url, from its own social card or else what was
+/// scraped from its destination. Not a visit, so nothing is logged as a redirect.
+async fn unfurl_micro_url(
+    req: &Request<AppState>,
+    id: &str,
+    data: &MicroUrlData,
+) -> tide::Result<Response> {
+    let now = chrono::Utc::now();
+    let long_url = data.long_url_at(now);
+    if blocked_url(req, Some(id), &[long_url], "redirect")
+        .await
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?
+    {
+        return Ok(pages::html_response(
+            StatusCode::Gone,
+            pages::link_disabled(),
+        ));
+    }
+    let metadata = req
+        .state()
+        .metadata_dao
+        .get_metadata(id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .filter(|m| m.long_url == long_url);
+    let social = data.social.clone().unwrap_or_default();
+    let micro_url = req.state().url_dao.micro_url_info(id, data).micro_url;
+    let html = pages::unfurl(&pages::Unfurl {
+        micro_url: &micro_url,
+        long_url,
+        title: social
+            .title
+            .as_deref()
+            .or_else(|| metadata.as_ref().and_then(|m| m.display_title())),
+        description: social
+            .description
+            .as_deref()
+            .or_else(|| metadata.as_ref().and_then(|m| m.og_description.as_deref())),
+        image: social
+            .image
+            .as_deref()
+            .or_else(|| metadata.as_ref().and_then(|m| m.og_image.as_deref())),
+    });
+    Ok(pages::html_response(StatusCode::Ok, html))
+}
+
+/// Shows where a micro url goes without going there, so nothing is logged as a redirect.
+async fn preview_micro_url(req: &Request<AppState>, id: &str) -> tide::Result<Response> {
+    let url_dao = &req.state().url_dao;
+    let found: Option<MicroUrlData> = url_dao
+        .get_micro_url(id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    match found.filter(|data| served_on_request_host(req, data)) {
+        Some(data) => {
+            let now = chrono::Utc::now();
+            let micro_url = url_dao.micro_url_info(id, &data).micro_url;
+            // where protected and not yet active links go is not shown
+            let hidden = data.password_hash.is_some() || !data.is_active(now);
+            let long_urls = if hidden {
+                vec![]
+            } else if data.destinations.is_empty() {
+                vec![data.long_url_at(now)]
+            } else {
+                data.destinations
+                    .iter()
+                    .map(|d| d.long_url.as_str())
+                    .collect()
+            };
+            // only when it is about the destination shown
+            let metadata = match !hidden && data.destinations.is_empty() {
+                true => req
+                    .state()
+                    .metadata_dao
+                    .get_metadata(id)
+                    .await
+                    .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+                    .filter(|m| m.long_url == data.long_url_at(now)),
+                false => None,
+            };
+            let html = pages::preview(&pages::Preview {
+                micro_url: &micro_url,
+                long_urls,
+                created_at: data.created_at(),
+                password_protected: data.password_hash.is_some(),
+                not_before: data.not_before.filter(|nb| *nb > now),
+                metadata: metadata.as_ref(),
+            });
+            Ok(pages::html_response(StatusCode::Ok, html))
+        }
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+/// Checks the password for a protected micro url, on success sets the access cookie and sends the
+/// visitor back to the micro url to be redirected.
+async fn unlock_micro_url(mut req: Request<AppState>) -> tide::Result<Response> {
+    let form: PasswordForm = match req.body_form().await {
+        Ok(f) => f,
+        Err(_) => return Ok(Response::new(StatusCode::UnprocessableEntity)),
+    };
+    let id: String = id_param(&req);
+    let state = req.state();
+    let ip = client_ip(&req, state.app_config.trusted_proxy_hops);
+    let attempts_key = format!("{}/{}", id, ip.as_deref().unwrap_or(""));
+    let now = chrono::Utc::now();
+
+    let found: Option<MicroUrlData> = state
+        .url_dao
+        .get_micro_url(&id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    let password_hash = match found
+        .filter(|data| served_on_request_host(&req, data))
+        .and_then(|d| d.password_hash)
+    {
+        Some(h) => h,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+
+    // guesses spread over many ips slow down, without locking out whoever knows the password
+    if state.link_failed_attempts.is_blocked(&id, now) {
+        let delay = state.app_config.password_link_delay_ms;
+        async_std::task::sleep(std::time::Duration::from_millis(delay)).await;
+    }
+    let outcome = if state.failed_attempts.is_blocked(&attempts_key, now) {
+        "blocked"
+    } else if async_std::task::spawn_blocking(move || {
+        verify_password(&form.password, &password_hash)
+    })
+    .await
+    {
+        "ok"
+    } else {
+        state.failed_attempts.record_failure(&attempts_key, now);
+        state.link_failed_attempts.record_failure(&id, now);
+        "failed"
+    };
+    if outcome != "ok" {
+        let event = PasswordEvent {
+            id: id.to_owned(),
+            outcome,
+            ip,
+        };
+        state
+            .event_logger
+            .log_event("password", &event)
+            .await
+            .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+        return Ok(if outcome == "blocked" {
+            pages::html_response(StatusCode::TooManyRequests, pages::too_many_attempts())
+        } else {
+            pages::html_response(StatusCode::Unauthorized, pages::password_form(&id, true))
+        });
+    }
+
+    state.failed_attempts.reset(&attempts_key);
+    let signer = &state.access_signer;
+    let cookie = Cookie::build(ACCESS_COOKIE_NAME, signer.sign(&id, now))
+        .path(format!("/{}", encode_id(&id)))
+        .max_age(Duration::seconds(signer.ttl().num_seconds()))
+        .http_only(true)
+        .secure(state.app_config.cookie_secure)
+        .finish();
+    let mut response: Response = Redirect::see_other(format!("/{}", encode_id(&id))).into();
+    response.insert_cookie(cookie);
+    Ok(response)
+}
+
+/// Scrapes the destination in the background, the micro url works whether or not this does.
+/// Destinations behind a password or not live yet are left alone, previews must not give them
+/// away.
+fn fetch_metadata(state: &AppState, id: &str, data: &MicroUrlData) {
+    if data.password_hash.is_some() || !data.is_active(chrono::Utc::now()) {
+        return;
+    }
+    let (metadata_dao, fetcher) = (state.metadata_dao.clone(), state.metadata_fetcher.clone());
+    let (id, long_url) = (id.to_owned(), data.long_url.to_owned());
+    async_std::task::spawn(async move {
+        let url = long_url.to_owned();
+        let fetched = async_std::task::spawn_blocking(move || fetcher.fetch(&url)).await;
+        let stored = match fetched {
+            Ok(metadata) => metadata_dao.set_metadata(&id, &metadata).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            info!("no metadata for [{}] from [{}], {}", id, long_url, e);
+        }
+    });
+}
+
+/// Checks `urls` against the url policy, logging a block event for the first one that is blocked.
+#[throws(anyhow::Error)]
+async fn blocked_url(
+    req: &Request<AppState>,
+    id: Option<&str>,
+    urls: &[&str],
+    stage: &'static str,
+) -> bool {
+    let policy = &req.state().url_policy;
+    let blocked = urls
+        .iter()
+        .find_map(|url| policy.blocked_by(url).map(|rule| (*url, rule)));
+    match blocked {
+        Some((url, rule)) => {
+            warn!("blocked [{}] by [{}] on {}", url, rule, stage);
+            let event = BlockEvent {
+                id,
+                url,
+                rule,
+                stage,
+                ip: client_ip(req, req.state().app_config.trusted_proxy_hops),
+            };
+            req.state().event_logger.log_event("block", &event).await?;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Micro urls on an extra domain only resolve on that host, the rest on any other host.
+fn served_on_request_host(req: &Request<AppState>, data: &MicroUrlData) -> bool {
+    let url_dao = &req.state().url_dao;
+    let request_domain = req
+        .host()
+        .map(|h| h.to_ascii_lowercase())
+        .filter(|h| url_dao.has_domain(h));
+    request_domain == data.domain
+}
+
+fn strip_port(host: &str) -> &str {
+    host.split(':').next().unwrap_or(host)
+}
+
+fn app_link_response(app_link: &AppLink, platform: Platform, web_url: String) -> Response {
+    let (app_url, store_url) = match platform {
+        Platform::Ios => (&app_link.ios_url, &app_link.ios_store_url),
+        Platform::Android => (&app_link.android_url, &app_link.android_store_url),
+        Platform::Other => (&None, &None),
+    };
+    match app_url {
+        Some(app_url) => {
+            let fallback = store_url.as_deref().unwrap_or(&web_url);
+            pages::html_response(StatusCode::Ok, pages::app_interstitial(app_url, fallback))
+        }
+        None => Redirect::temporary(web_url).into(),
+    }
+}
+
+/// Records a conversion for the visitor in the `_utrakr` cookie, meant to be posted from the
+/// destination once the visitor did whatever counts as converting. Not a GET, so that images
+/// and prefetches on other pages can not record one.
+async fn convert_micro_url(req: Request<AppState>) -> tide::Result<Response> {
+    let id: &str = &id_param(&req);
+    let url_dao = &req.state().url_dao;
+
+    let cookie = match req.cookie(COOKIE_NAME) {
+        Some(c) => c,
+        None => return Ok(Response::new(StatusCode::NoContent)),
+    };
+    let found: Option<MicroUrlData> = url_dao
+        .get_micro_url(id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    match found.filter(|data| served_on_request_host(&req, data)) {
+        Some(data) => {
+            let event = ConversionEvent {
+                id: id.to_owned(),
+                variant: choose_variant(cookie.value(), id, &data.destinations),
+                cookie: cookie.value().to_owned(),
+            };
+            let event_logger = &req.state().event_logger;
+            event_logger
+                .log_event("conversion", &event)
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            Ok(Response::new(StatusCode::NoContent))
+        }
         None => Ok(Response::new(StatusCode::NotFound)),
     }
 }
 
+/// Qr code for the micro url, rendered here so the links never leave us.
+async fn qr_code(req: Request<AppState>) -> tide::Result<Response> {
+    let id: &str = &id_param(&req);
+    let url_dao = &req.state().url_dao;
+    let request: QrRequest = req.query()?;
+    let options = match QrOptions::from_request(&request) {
+        Ok(o) => o,
+        Err(e) => {
+            return Ok(Response::builder(StatusCode::UnprocessableEntity)
+                .body(e.to_string())
+                .build())
+        }
+    };
+    let logo = if options.logo {
+        match req.state().qr_logo {
+            Some(ref logo) => Some(logo.clone()),
+            None => return Ok(Response::new(StatusCode::UnprocessableEntity)),
+        }
+    } else {
+        None
+    };
+
+    let found: Option<MicroUrlData> = url_dao
+        .get_micro_url(id)
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    let micro_url = match found.filter(|data| served_on_request_host(&req, data)) {
+        Some(data) => url_dao.micro_url_info(id, &data).micro_url,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let content_type = options.content_type();
+    let image =
+        async_std::task::spawn_blocking(move || qr::render(&micro_url, &options, logo.as_deref()))
+            .await
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    Ok(Response::builder(StatusCode::Ok)
+        .body(image)
+        .content_type(content_type)
+        .header("cache-control", "public, max-age=86400")
+        .build())
+}
+
 async fn ruok(_req: Request<AppState>) -> tide::Result<Response> {
     Ok(Response::builder(StatusCode::Ok)
         .body("imok".to_owned())
         .build())
 }
 
+/// Reloads the url policy file now rather than on the next check.
+async fn reload_url_policy(req: Request<AppState>) -> tide::Result<Response> {
+    if read_admin(&req).is_none() {
+        return Ok(Response::new(StatusCode::Unauthorized));
+    }
+    match req.state().url_policy.reload() {
+        Ok(()) => Ok(Response::new(StatusCode::Ok)),
+        Err(e) => Ok(Response::builder(StatusCode::UnprocessableEntity)
+            .body(e.to_string())
+            .build()),
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DecodedIdResponse<'a> {
+    id: &'a str,
+    #[serde(flatten)]
+    decoded: DecodedId,
+    /// whether the micro url is still stored
+    exists: bool,
+}
+
+/// What an id says about when it was made, for looking into links whose data is gone.
+#[throws(http_types::Error)]
+async fn decode_id(req: Request<AppState>) -> Response {
+    if read_admin(&req).is_none() {
+        return Response::new(StatusCode::Unauthorized);
+    }
+    let id: &str = &id_param(&req);
+    let url_dao = &req.state().url_dao;
+    match url_dao.decode_id(id) {
+        Some(decoded) => {
+            let exists = url_dao
+                .get_micro_url(id)
+                .await
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?
+                .is_some();
+            let response = DecodedIdResponse {
+                id,
+                decoded,
+                exists,
+            };
+            Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&response)?)
+                .build()
+        }
+        None => Response::new(StatusCode::NotFound),
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct ViewsResponse {
     request: ViewsRequest,
@@ -222,9 +1263,29 @@ struct ViewsResponse {
     data: ViewsData,
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 struct UserAccount {
     email: String,
+    /// id of the api key used instead of a google login
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    api_key: Option<String>,
+    /// what the api key may do, a google login may do everything
+    #[serde(skip)]
+    scopes: Option<BTreeSet<Scope>>,
+}
+
+impl UserAccount {
+    fn new(email: &str) -> UserAccount {
+        UserAccount {
+            email: email.to_owned(),
+            api_key: None,
+            scopes: None,
+        }
+    }
+
+    fn allows(&self, scope: Scope) -> bool {
+        self.scopes.as_ref().is_none_or(|s| s.contains(&scope))
+    }
 }
 
 #[throws(http_types::Error)]
@@ -232,8 +1293,12 @@ async fn views(req: Request<AppState>) -> Response {
     let request: ViewsRequest = req.query()?;
     let views_dao = &req.state().views_dao;
 
-    if let Some(account) = read_auth(&req) {
-        let data = views_dao.get_views_data(&request)?;
+    if let Some(account) = read_auth(&req, Scope::StatsRead) {
+        let ids = match viewable_ids(&req, &account, &request).await? {
+            Some(ids) => ids,
+            None => return Response::new(StatusCode::NotFound),
+        };
+        let data = views_dao.get_views_data(&request, &ids)?;
         Response::builder(StatusCode::Ok)
             .body(Body::from_json(&ViewsResponse {
                 account,
@@ -246,36 +1311,809 @@ async fn views(req: Request<AppState>) -> Response {
     }
 }
 
-fn read_auth(req: &Request<AppState>) -> Option<UserAccount> {
-    if let Some(auth) = req.header("authorization") {
-        if auth.as_str().starts_with("Bearer ") {
-            let jwt = &auth.as_str()["Bearer ".len()..];
-            if !jwt.is_empty() {
-                let claim = get_claim_from_google(jwt);
-                return claim.map(|c| UserAccount { email: c.email });
-            } else {
-                warn!("found bearer with no token")
-            }
-        } else {
-            warn!("found authorization with no bearer")
-        }
+/// Which micro urls the views are counted for: the requested one, a workspace's, or the caller's.
+/// None when the caller may not see them.
+#[throws(anyhow::Error)]
+async fn viewable_ids(
+    req: &Request<AppState>,
+    account: &UserAccount,
+    request: &ViewsRequest,
+) -> Option<HashSet<String>> {
+    if let Some(id) = request.id() {
+        return authorized_link(req, account, id, Role::Viewer)
+            .await?
+            .map(|_| vec![id.to_owned()].into_iter().collect());
     }
-    None
+    if let Some(workspace) = request.workspace() {
+        return match workspace_role(req, account, workspace).await? {
+            Some(_) => Some(req.state().workspace_dao.links(workspace).await?),
+            None => None,
+        };
+    }
+    Some(req.state().url_dao.owned_links(&account.email).await?)
 }
 
-#[async_std::main]
-async fn main() -> tide::Result<()> {
-    let app = App {
-        name: APP_NAME.to_owned(),
-    };
-    let app_config: AppConfig = StructOpt::from_args();
-    tide::log::with_level(app_config.log_level);
+#[derive(Debug, serde::Serialize)]
+struct LinkResponse {
+    info: MicroUrlInfo,
+    data: LinkData,
+    /// the last check of the current destination, none until it was checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<LinkHealth>,
+    /// scraped from the current destination, none until it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<PageMetadata>,
+}
 
-    info!("loading config {:?}", app_config);
+impl LinkResponse {
+    #[throws(anyhow::Error)]
+    async fn new(req: &Request<AppState>, id: &str, data: MicroUrlData) -> LinkResponse {
+        let now = chrono::Utc::now();
+        let health = req
+            .state()
+            .health_dao
+            .get_health(id)
+            .await?
+            .filter(|h| h.long_url == data.long_url_at(now));
+        let metadata = req
+            .state()
+            .metadata_dao
+            .get_metadata(id)
+            .await?
+            .filter(|m| m.long_url == data.long_url_at(now));
+        LinkResponse {
+            info: req.state().url_dao.micro_url_info(id, &data),
+            data: LinkData::new(data, now),
+            health,
+            metadata,
+        }
+    }
+}
 
-    let ulid_generator = Arc::new(Mutex::new(UlidGenerator::new()));
+/// What the links api shows of a `MicroUrlData`, never the password hash.
+#[derive(Debug, serde::Serialize)]
+struct LinkData {
+    long_url: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    destinations: Vec<Destination>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    app_link: Option<AppLink>,
+    password_protected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    not_before: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    schedule: Vec<ScheduledDestination>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workspace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    tags: BTreeSet<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    social: Option<SocialCard>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<chrono::DateTime<chrono::Utc>>,
+    active: bool,
+    current_long_url: String,
+    version: u32,
+}
+
+impl LinkData {
+    /// Every web url with a unicode host, the way people typed it.
+    fn new(data: MicroUrlData, now: chrono::DateTime<chrono::Utc>) -> LinkData {
+        LinkData {
+            active: data.is_active(now),
+            current_long_url: unicode_url(data.long_url_at(now)),
+            version: data.current_version(),
+            created_at: data.created_at(),
+            password_protected: data.password_hash.is_some(),
+            long_url: unicode_url(&data.long_url),
+            destinations: data
+                .destinations
+                .into_iter()
+                .map(|d| Destination {
+                    long_url: unicode_url(&d.long_url),
+                    ..d
+                })
+                .collect(),
+            app_link: data.app_link.map(|a| AppLink {
+                ios_store_url: a.ios_store_url.as_deref().map(unicode_url),
+                android_store_url: a.android_store_url.as_deref().map(unicode_url),
+                ..a
+            }),
+            owner: data.owner,
+            not_before: data.not_before,
+            schedule: data
+                .schedule
+                .into_iter()
+                .map(|s| ScheduledDestination {
+                    long_url: unicode_url(&s.long_url),
+                    ..s
+                })
+                .collect(),
+            domain: data.domain,
+            workspace: data.workspace,
+            title: data.title,
+            notes: data.notes,
+            tags: data.tags,
+            social: data.social,
+        }
+    }
+}
+
+#[throws(http_types::Error)]
+async fn get_link(req: Request<AppState>) -> Response {
+    let id: &str = &id_param(&req);
+
+    if let Some(account) = read_auth(&req, Scope::LinksRead) {
+        match authorized_link(&req, &account, id, Role::Viewer).await? {
+            Some(data) => Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&LinkResponse::new(&req, id, data).await?)?)
+                .build(),
+            None => Response::new(StatusCode::NotFound),
+        }
+    } else {
+        Response::new(StatusCode::Unauthorized)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct UpdateLinkRequest {
+    /// a new version of the destination
+    long_url: Option<String>,
+    /// the fields below are replaced as given, an empty title or notes clears them
+    title: Option<String>,
+    notes: Option<String>,
+    tags: Option<Vec<String>>,
+    /// an empty one goes back to what was scraped from the destination
+    social: Option<SocialCard>,
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.trim().is_empty() {
+        None
+    } else {
+        Some(s.to_owned())
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct RollbackRequest {
+    version: u32,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct VersionAtRequest {
+    at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct VersionsResponse {
+    id: String,
+    versions: Vec<DestinationVersion>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LinkChangeEvent<'a> {
+    id: &'a str,
+    account: &'a UserAccount,
+    version: &'a DestinationVersion,
+}
+
+/// Changes the destination of a micro url, keeping the old one in its history, and its details.
+#[throws(http_types::Error)]
+async fn update_link(mut req: Request<AppState>) -> Response {
+    let mut request: UpdateLinkRequest = req.body_json().await?;
+    let id = id_param(&req);
+    if let Some(ref mut long_url) = request.long_url {
+        match checked_destination(&req, &id, long_url).await? {
+            Ok(resolved) => *long_url = resolved,
+            Err(response) => return response,
+        }
+    }
+    change_link(&req, &id, |data, account, now| {
+        // only a new destination is a new version, not new details
+        if let Some(long_url) = request.long_url.as_ref().filter(|u| **u != data.long_url) {
+            data.change_long_url(long_url, Some(&account.email), now);
+        }
+        if let Some(ref title) = request.title {
+            data.title = non_empty(title);
+        }
+        if let Some(ref notes) = request.notes {
+            data.notes = non_empty(notes);
+        }
+        if let Some(ref tags) = request.tags {
+            data.set_tags(tags);
+        }
+        if let Some(ref social) = request.social {
+            data.social = Some(social.clone()).filter(|s| !s.is_empty());
+        }
+        request.long_url.is_some()
+            || request.title.is_some()
+            || request.notes.is_some()
+            || request.tags.is_some()
+            || request.social.is_some()
+    })
+    .await?
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LinksResponse {
+    request: LinkSearchRequest,
+    links: Vec<LinkResponse>,
+}
+
+/// Searches the caller's links and those of their workspaces, newest first.
+#[throws(http_types::Error)]
+async fn search_links(req: Request<AppState>) -> Response {
+    let request: LinkSearchRequest = req.query()?;
+    let url_dao = &req.state().url_dao;
+    let account = match read_auth(&req, Scope::LinksRead) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+
+    let ids = match searchable_ids(&req, &account, request.workspace()).await? {
+        Some(ids) => ids,
+        None => return Response::new(StatusCode::NotFound),
+    };
+    let ids: Vec<String> = ids.into_iter().collect();
+    let mut found: Vec<(String, MicroUrlData)> = url_dao
+        .get_micro_urls(&ids)
+        .await?
+        .into_iter()
+        .filter(|(_, data)| request.matches(data))
+        .collect();
+    found.sort_by(|(a_id, a), (b_id, b)| (b.created_at(), b_id).cmp(&(a.created_at(), a_id)));
+    let mut links = Vec::with_capacity(found.len().min(request.limit()));
+    for (id, data) in found.into_iter().take(request.limit()) {
+        links.push(LinkResponse::new(&req, &id, data).await?);
+    }
+    Response::builder(StatusCode::Ok)
+        .body(Body::from_json(&LinksResponse { request, links })?)
+        .build()
+}
+
+/// The ids of a workspace's links, or of the caller's own links and those of all their workspaces.
+/// None when the caller is not a member of the workspace.
+#[throws(anyhow::Error)]
+async fn searchable_ids(
+    req: &Request<AppState>,
+    account: &UserAccount,
+    workspace: Option<&str>,
+) -> Option<HashSet<String>> {
+    let workspace_dao = &req.state().workspace_dao;
+    if let Some(workspace) = workspace {
+        return match workspace_role(req, account, workspace).await? {
+            Some(_) => Some(workspace_dao.links(workspace).await?),
+            None => None,
+        };
+    }
+    let mut ids = req.state().url_dao.owned_links(&account.email).await?;
+    for w in workspace_dao.member_workspaces(&account.email).await? {
+        ids.extend(workspace_dao.links(&w.id).await?);
+    }
+    Some(ids)
+}
+
+#[throws(http_types::Error)]
+async fn rollback_link(mut req: Request<AppState>) -> Response {
+    let request: RollbackRequest = req.body_json().await?;
+    let id = id_param(&req);
+    let old_url = match read_auth(&req, Scope::LinksWrite) {
+        Some(account) => authorized_link(&req, &account, &id, Role::Editor)
+            .await?
+            .and_then(|data| {
+                data.versions()
+                    .into_iter()
+                    .find(|v| v.version == request.version)
+            })
+            .map(|v| v.long_url),
+        None => None,
+    };
+    // where the old destination leads may have changed since
+    let long_url = match old_url {
+        Some(old_url) => match checked_destination(&req, &id, &old_url).await? {
+            Ok(resolved) => Some(resolved),
+            Err(response) => return response,
+        },
+        None => None,
+    };
+    change_link(&req, &id, |data, account, now| match long_url {
+        Some(ref long_url) => data.rollback(request.version, long_url, Some(&account.email), now),
+        None => false,
+    })
+    .await?
+}
+
+/// The new destination of micro url `id` resolved as on create, or the response rejecting it.
+#[throws(http_types::Error)]
+async fn checked_destination(
+    req: &Request<AppState>,
+    id: &str,
+    long_url: &str,
+) -> Result<String, Response> {
+    let url_dao = &req.state().url_dao;
+    let follower = &req.state().shortener_follower;
+    let resolved = match resolve_chain(url_dao, follower, long_url, Some(id)).await? {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return Err(Response::builder(StatusCode::UnprocessableEntity)
+                .body(e.to_string())
+                .build())
+        }
+    };
+    if blocked_url(req, Some(id), &[&resolved], "change").await? {
+        return Err(Response::new(StatusCode::Forbidden));
+    }
+    Ok(resolved)
+}
+
+/// Applies `change` to a micro url the caller may edit, and logs the new version.
+#[throws(http_types::Error)]
+async fn change_link<F>(req: &Request<AppState>, id: &str, change: F) -> Response
+where
+    F: FnOnce(&mut MicroUrlData, &UserAccount, chrono::DateTime<chrono::Utc>) -> bool,
+{
+    let url_dao = &req.state().url_dao;
+    let account = match read_auth(req, Scope::LinksWrite) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    let mut data = match authorized_link(req, &account, id, Role::Editor).await? {
+        Some(data) => data,
+        None => return Response::new(StatusCode::NotFound),
+    };
+    let old = data.clone();
+    if !change(&mut data, &account, chrono::Utc::now()) {
+        return Response::new(StatusCode::UnprocessableEntity);
+    }
+    // someone else changed or deleted it since it was read
+    if !url_dao.update_micro_url(id, &old, &data).await? {
+        return Response::new(StatusCode::Conflict);
+    }
+
+    if data.current_version() != old.current_version() {
+        fetch_metadata(req.state(), id, &data);
+        let versions = data.versions();
+        let event = LinkChangeEvent {
+            id,
+            account: &account,
+            version: versions.last().expect("always at least one version"),
+        };
+        req.state().event_logger.log_event("change", &event).await?;
+    }
+
+    Response::builder(StatusCode::Ok)
+        .body(Body::from_json(&LinkResponse::new(req, id, data).await?)?)
+        .build()
+}
+
+#[throws(http_types::Error)]
+async fn link_versions(req: Request<AppState>) -> Response {
+    let id: &str = &id_param(&req);
+
+    if let Some(account) = read_auth(&req, Scope::LinksRead) {
+        match authorized_link(&req, &account, id, Role::Viewer).await? {
+            Some(data) => Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&VersionsResponse {
+                    id: id.to_owned(),
+                    versions: data.versions(),
+                })?)
+                .build(),
+            None => Response::new(StatusCode::NotFound),
+        }
+    } else {
+        Response::new(StatusCode::Unauthorized)
+    }
+}
+
+/// Where the micro url pointed at a point in time.
+#[throws(http_types::Error)]
+async fn link_version_at(req: Request<AppState>) -> Response {
+    let request: VersionAtRequest = req.query()?;
+    let id: &str = &id_param(&req);
+
+    if let Some(account) = read_auth(&req, Scope::LinksRead) {
+        match authorized_link(&req, &account, id, Role::Viewer)
+            .await?
+            .and_then(|data| data.version_at(request.at))
+        {
+            Some(version) => Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&version)?)
+                .build(),
+            None => Response::new(StatusCode::NotFound),
+        }
+    } else {
+        Response::new(StatusCode::Unauthorized)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct CreateWorkspaceRequest {
+    name: String,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct WorkspacesResponse {
+    workspaces: Vec<Workspace>,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct MemberRequest {
+    email: String,
+    /// removes the member when missing
+    role: Option<Role>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct WorkspaceEvent<'a> {
+    account: &'a UserAccount,
+    workspace: &'a Workspace,
+}
+
+#[throws(http_types::Error)]
+async fn create_workspace(mut req: Request<AppState>) -> Response {
+    let request: CreateWorkspaceRequest = req.body_json().await?;
+    let account = match read_auth(&req, Scope::WorkspacesWrite) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    let workspace = req
+        .state()
+        .workspace_dao
+        .create_workspace(&request.name, &account.email)
+        .await?;
+    let event = WorkspaceEvent {
+        account: &account,
+        workspace: &workspace,
+    };
+    req.state()
+        .event_logger
+        .log_event("workspace", &event)
+        .await?;
+    Response::builder(StatusCode::Ok)
+        .body(Body::from_json(&workspace)?)
+        .build()
+}
+
+#[throws(http_types::Error)]
+async fn list_workspaces(req: Request<AppState>) -> Response {
+    match read_auth(&req, Scope::WorkspacesRead) {
+        Some(account) => {
+            let workspaces = req
+                .state()
+                .workspace_dao
+                .member_workspaces(&account.email)
+                .await?;
+            Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&WorkspacesResponse { workspaces })?)
+                .build()
+        }
+        None => Response::new(StatusCode::Unauthorized),
+    }
+}
+
+#[throws(http_types::Error)]
+async fn get_workspace(req: Request<AppState>) -> Response {
+    let id: &str = req.param("workspace").unwrap_or("");
+    let account = match read_auth(&req, Scope::WorkspacesRead) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    match req.state().workspace_dao.get_workspace(id).await? {
+        Some(w) if w.role_of(&account.email).is_some() => Response::builder(StatusCode::Ok)
+            .body(Body::from_json(&w)?)
+            .build(),
+        _ => Response::new(StatusCode::NotFound),
+    }
+}
+
+/// Adds, changes or removes a member, only owners can do this.
+#[throws(http_types::Error)]
+async fn set_workspace_member(mut req: Request<AppState>) -> Response {
+    let request: MemberRequest = req.body_json().await?;
+    let id: &str = req.param("workspace").unwrap_or("");
+    let workspace_dao = &req.state().workspace_dao;
+    let account = match read_auth(&req, Scope::WorkspacesWrite) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    let previous = match workspace_dao.get_workspace(id).await? {
+        Some(w) => w,
+        None => return Response::new(StatusCode::NotFound),
+    };
+    match previous.role_of(&account.email) {
+        Some(Role::Owner) => (),
+        Some(_) => return Response::new(StatusCode::Forbidden),
+        None => return Response::new(StatusCode::NotFound),
+    }
+
+    let mut workspace = previous.clone();
+    if !workspace.set_role(&request.email, request.role) {
+        return Response::new(StatusCode::UnprocessableEntity);
+    }
+    workspace_dao
+        .update_workspace(&previous, &workspace)
+        .await?;
+    let event = WorkspaceEvent {
+        account: &account,
+        workspace: &workspace,
+    };
+    req.state()
+        .event_logger
+        .log_event("workspace", &event)
+        .await?;
+    Response::builder(StatusCode::Ok)
+        .body(Body::from_json(&workspace)?)
+        .build()
+}
+
+/// The micro url, when the caller has at least `role` on it.
+#[throws(anyhow::Error)]
+async fn authorized_link(
+    req: &Request<AppState>,
+    account: &UserAccount,
+    id: &str,
+    role: Role,
+) -> Option<MicroUrlData> {
+    match req.state().url_dao.get_micro_url(id).await? {
+        Some(data) => match link_role(req, account, &data).await? {
+            Some(r) if r >= role => Some(data),
+            _ => None,
+        },
+        None => None,
+    }
+}
+
+/// The owner can do anything, workspace members get their workspace role, and anonymous micro urls
+/// can be seen but not changed by admins.
+#[throws(anyhow::Error)]
+async fn link_role(
+    req: &Request<AppState>,
+    account: &UserAccount,
+    data: &MicroUrlData,
+) -> Option<Role> {
+    if data.owner.as_ref() == Some(&account.email) {
+        return Some(Role::Owner);
+    }
+    if let Some(ref workspace) = data.workspace {
+        return workspace_role(req, account, workspace).await?;
+    }
+    if data.owner.is_none() && is_admin(req, account) {
+        Some(Role::Viewer)
+    } else {
+        None
+    }
+}
+
+#[throws(anyhow::Error)]
+async fn workspace_role(
+    req: &Request<AppState>,
+    account: &UserAccount,
+    workspace: &str,
+) -> Option<Role> {
+    let workspace_dao = &req.state().workspace_dao;
+    workspace_dao
+        .get_workspace(workspace)
+        .await?
+        .and_then(|w| w.role_of(&account.email))
+}
+
+/// The caller, as checked by `Authenticate`, when they may do `scope`.
+fn read_auth(req: &Request<AppState>, scope: Scope) -> Option<UserAccount> {
+    req.ext::<UserAccount>()
+        .filter(|a| a.allows(scope))
+        .cloned()
+}
+
+/// The caller when they are one of the configured admins, with a google login or an admin api key.
+fn read_admin(req: &Request<AppState>) -> Option<UserAccount> {
+    read_auth(req, Scope::Admin).filter(|a| is_admin(req, a))
+}
+
+fn is_admin(req: &Request<AppState>, account: &UserAccount) -> bool {
+    account.allows(Scope::Admin)
+        && req
+            .state()
+            .app_config
+            .admin_emails
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(&account.email))
+}
+
+/// The caller when they logged in with google, api keys can not manage api keys.
+fn read_login(req: &Request<AppState>) -> Option<UserAccount> {
+    req.ext::<UserAccount>()
+        .filter(|a| a.api_key.is_none())
+        .cloned()
+}
 
+fn bearer_token(req: &Request<AppState>) -> Option<String> {
+    if let Some(auth) = req.header("authorization") {
+        if let Some(token) = auth.as_str().strip_prefix("Bearer ") {
+            if !token.is_empty() {
+                return Some(token.to_owned());
+            } else {
+                warn!("found bearer with no token")
+            }
+        } else {
+            warn!("found authorization with no bearer")
+        }
+    }
+    None
+}
+
+/// Checks the bearer token once per request, so that both the handlers and the rate limits know
+/// who is calling. The token is either one of our api keys or a google id token.
+struct Authenticate;
+
+#[async_trait]
+impl Middleware<AppState> for Authenticate {
+    async fn handle(&self, mut req: Request<AppState>, next: Next<'_, AppState>) -> tide::Result {
+        if let Some(token) = bearer_token(&req) {
+            if token.starts_with(API_KEY_PREFIX) {
+                let api_key = req
+                    .state()
+                    .api_key_dao
+                    .verify(&token, chrono::Utc::now())
+                    .await
+                    .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+                match api_key {
+                    Some(k) => {
+                        req.set_ext(Client::ApiKey(k.id.to_owned()));
+                        req.set_ext(UserAccount {
+                            email: k.owner,
+                            api_key: Some(k.id),
+                            scopes: Some(k.scopes),
+                        });
+                    }
+                    None => warn!("found unknown api key"),
+                }
+            } else if let Some(claim) =
+                google_claims(&token, req.state().app_config.google_client_id.as_deref()).await
+            {
+                req.set_ext(Client::Email(claim.email.to_owned()));
+                req.set_ext(UserAccount::new(&claim.email));
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct CreateApiKeyRequest {
+    name: String,
+    scopes: BTreeSet<Scope>,
+}
+
+/// The only time the key itself is shown.
+#[derive(Debug, serde::Serialize)]
+struct CreateApiKeyResponse {
+    key: String,
+    api_key: ApiKey,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ApiKeysResponse {
+    api_keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ApiKeyEvent<'a> {
+    account: &'a UserAccount,
+    id: &'a str,
+    /// created or revoked
+    action: &'static str,
+}
+
+#[throws(http_types::Error)]
+async fn create_api_key(mut req: Request<AppState>) -> Response {
+    let request: CreateApiKeyRequest = req.body_json().await?;
+    let account = match read_login(&req) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    if request.scopes.contains(&Scope::Admin) && !is_admin(&req, &account) {
+        return Response::new(StatusCode::Forbidden);
+    }
+    let (api_key, key) = ApiKey::generate(&request.name, &account.email, request.scopes);
+    req.state().api_key_dao.create_api_key(&api_key).await?;
+    let event = ApiKeyEvent {
+        account: &account,
+        id: &api_key.id,
+        action: "created",
+    };
+    req.state()
+        .event_logger
+        .log_event("api_key", &event)
+        .await?;
+    Response::builder(StatusCode::Ok)
+        .body(Body::from_json(&CreateApiKeyResponse {
+            key,
+            api_key: api_key.redacted(),
+        })?)
+        .build()
+}
+
+#[throws(http_types::Error)]
+async fn list_api_keys(req: Request<AppState>) -> Response {
+    match read_login(&req) {
+        Some(account) => {
+            let api_keys = req
+                .state()
+                .api_key_dao
+                .owned_api_keys(&account.email)
+                .await?;
+            Response::builder(StatusCode::Ok)
+                .body(Body::from_json(&ApiKeysResponse { api_keys })?)
+                .build()
+        }
+        None => Response::new(StatusCode::Unauthorized),
+    }
+}
+
+#[throws(http_types::Error)]
+async fn revoke_api_key(req: Request<AppState>) -> Response {
+    let id: &str = req.param("key").unwrap_or("");
+    let account = match read_login(&req) {
+        Some(a) => a,
+        None => return Response::new(StatusCode::Unauthorized),
+    };
+    if !req
+        .state()
+        .api_key_dao
+        .revoke_api_key(&account.email, id)
+        .await?
+    {
+        return Response::new(StatusCode::NotFound);
+    }
+    let event = ApiKeyEvent {
+        account: &account,
+        id,
+        action: "revoked",
+    };
+    req.state()
+        .event_logger
+        .log_event("api_key", &event)
+        .await?;
+    Response::new(StatusCode::NoContent)
+}
+
+/// The app with all its routes, without starting anything in the background.
+#[throws(anyhow::Error)]
+async fn new_app(app_config: AppConfig) -> tide::Server<AppState> {
+    let ulid_generator = Arc::new(Mutex::new(UlidGenerator::new()));
     let url_dao = UrlDao::new(&app_config)?;
+    let workspace_dao = WorkspaceDao::new(&app_config.redis_urls_client_conn)?;
+    let api_key_dao = ApiKeyDao::new(&app_config.redis_urls_client_conn)?;
+    let health_dao = HealthDao::new(&app_config.redis_urls_client_conn)?;
+    let metadata_dao = MetadataDao::new(&app_config.redis_urls_client_conn)?;
+    let idempotency_dao = IdempotencyDao::new(
+        &app_config.redis_urls_client_conn,
+        app_config.idempotency_ttl_hours * 60 * 60,
+    )?;
+    let metadata_fetcher = MetadataFetcher {
+        timeout: std::time::Duration::from_secs(app_config.metadata_timeout_seconds),
+        max_bytes: app_config.metadata_max_kb * 1024,
+        allow_private: app_config.metadata_allow_private_ips,
+    };
+    let shortener_follower = ShortenerFollower {
+        hosts: app_config
+            .follow_shortener_hosts
+            .iter()
+            .map(|h| h.trim().to_ascii_lowercase())
+            .collect(),
+        max_hops: app_config.follow_shortener_hops,
+        timeout: std::time::Duration::from_secs(app_config.metadata_timeout_seconds),
+        allow_private: app_config.metadata_allow_private_ips,
+    };
     let redirect = Redirect::permanent(app_config.redirect_homepage.to_owned());
     let event_logger: EventLogger = EventLogger::new(
         &app_config.event_log_folder,
@@ -285,25 +2123,132 @@ async fn main() -> tide::Result<()> {
     .await?;
 
     let views_dao = ViewsDao::from_path(&app_config.event_log_folder);
-    event_logger.log_event("startup", &Startup { app }).await?;
+    let qr_logo = match app_config.qr_logo_path {
+        Some(ref path) => Some(Arc::new(QrLogo::load(path)?)),
+        None => None,
+    };
+    let access_signer = AccessSigner::new(
+        app_config.cookie_signing_key.as_ref().map(|k| k.0.as_str()),
+        chrono::Duration::minutes(app_config.password_access_minutes),
+    );
+    let failed_attempts = FailedAttempts::new(
+        app_config.password_max_attempts,
+        chrono::Duration::minutes(app_config.password_attempts_window_minutes),
+    );
+    let link_failed_attempts = FailedAttempts::new(
+        app_config.password_max_attempts_per_link,
+        chrono::Duration::minutes(app_config.password_attempts_window_minutes),
+    );
+    let url_policy = UrlPolicyFile::load(app_config.url_policy_path.as_deref())?;
+    let rate_limit_store = if app_config.rate_limit_redis {
+        RateLimitStore::redis(&app_config.redis_urls_client_conn)?
+    } else {
+        RateLimitStore::memory()
+    };
+    let create_limit = RateLimit::new(
+        "create",
+        app_config.rate_limit_create,
+        rate_limit_store.clone(),
+        app_config.trusted_proxy_hops,
+    );
+    let redirect_limit = RateLimit::new(
+        "redirect",
+        app_config.rate_limit_redirect,
+        rate_limit_store.clone(),
+        app_config.trusted_proxy_hops,
+    );
+    let suggest_limit = RateLimit::new(
+        "suggest",
+        app_config.rate_limit_suggest,
+        rate_limit_store.clone(),
+        app_config.trusted_proxy_hops,
+    );
+    let api_limit = RateLimit::new(
+        "api",
+        app_config.rate_limit_api,
+        rate_limit_store,
+        app_config.trusted_proxy_hops,
+    );
     let app_state = AppState {
         app_config,
         url_dao,
+        workspace_dao,
+        api_key_dao,
+        health_dao,
+        metadata_dao,
+        metadata_fetcher,
+        scraped_when_active: Arc::new(std::sync::Mutex::new(HashSet::new())),
+        shortener_follower,
+        idempotency_dao,
         views_dao,
         event_logger,
         ulid_generator,
+        access_signer,
+        failed_attempts,
+        link_failed_attempts,
+        qr_logo,
+        url_policy,
+        suggest_limit,
     };
 
     // app
     let mut app = tide::with_state(app_state);
     app.at("/private/ruok").get(ruok);
-    app.at("/").get(redirect).post(create_micro_url);
-    app.at("/:id").get(redirect_micro_url);
-    app.at("/api/views").get(views);
+    app.at("/private/url-policy/reload").post(reload_url_policy);
+    app.at("/private/ids/:id").get(decode_id);
+    app.at("/").get(redirect);
+    app.at("/").with(create_limit).post(create_micro_url);
+    app.at("/:id")
+        .with(redirect_limit.clone())
+        .get(redirect_micro_url)
+        .post(unlock_micro_url);
+    app.at("/:id/convert")
+        .with(redirect_limit.clone())
+        .post(convert_micro_url);
+    app.at("/:id/qr").with(redirect_limit.clone()).get(qr_code);
+    app.at("/:id/preview").with(redirect_limit).get(preview);
+    app.at("/api/views").with(api_limit.clone()).get(views);
+    app.at("/api/links")
+        .with(api_limit.clone())
+        .get(search_links);
+    app.at("/api/links/:id")
+        .with(api_limit.clone())
+        .get(get_link)
+        .put(update_link);
+    app.at("/api/links/:id/versions")
+        .with(api_limit.clone())
+        .get(link_versions);
+    app.at("/api/links/:id/rollback")
+        .with(api_limit.clone())
+        .post(rollback_link);
+    app.at("/api/links/:id/at")
+        .with(api_limit.clone())
+        .get(link_version_at);
+    app.at("/api/workspaces")
+        .with(api_limit.clone())
+        .get(list_workspaces)
+        .post(create_workspace);
+    app.at("/api/workspaces/:workspace")
+        .with(api_limit.clone())
+        .get(get_workspace);
+    app.at("/api/keys")
+        .with(api_limit.clone())
+        .get(list_api_keys)
+        .post(create_api_key);
+    app.at("/api/keys/:key")
+        .with(api_limit.clone())
+        .delete(revoke_api_key);
+    app.at("/api/workspaces/:workspace/members")
+        .with(api_limit)
+        .put(set_workspace_member);
 
     // cors
     let cors = CorsMiddleware::new()
-        .allow_methods("GET, POST, OPTIONS".parse::<HeaderValue>().unwrap())
+        .allow_methods(
+            "GET, POST, PUT, DELETE, OPTIONS"
+                .parse::<HeaderValue>()
+                .unwrap(),
+        )
         .allow_origin(Origin::from("*"))
         .allow_credentials(false);
     app.with(cors);
@@ -311,7 +2256,593 @@ async fn main() -> tide::Result<()> {
     // logs
     app.with(tide::log::LogMiddleware::new());
 
+    // who is calling, before the rate limits on the routes
+    app.with(Authenticate);
+
+    app
+}
+
+#[async_std::main]
+async fn main() -> tide::Result<()> {
+    let app_config: AppConfig = StructOpt::from_args();
+    tide::log::with_level(app_config.log_level);
+
+    info!("loading config {:?}", app_config);
+    if app_config.google_client_id.is_none() {
+        warn!("no google client id, google logins are refused");
+    }
+
+    let app = new_app(app_config).await?;
+    let state = app.state();
+    if state.app_config.url_policy_path.is_some() {
+        let url_policy = state.url_policy.clone();
+        let every = std::time::Duration::from_secs(state.app_config.url_policy_reload_seconds);
+        async_std::task::spawn(async move {
+            loop {
+                async_std::task::sleep(every).await;
+                url_policy.reload_if_modified();
+            }
+        });
+    }
+    if state.app_config.health_check_minutes > 0 {
+        HealthChecker {
+            url_dao: state.url_dao.clone(),
+            health_dao: state.health_dao.clone(),
+            event_logger: state.event_logger.clone(),
+            interval: chrono::Duration::minutes(state.app_config.health_check_minutes),
+            concurrency: state.app_config.health_check_concurrency,
+            timeout: std::time::Duration::from_secs(state.app_config.health_check_timeout_seconds),
+            allow_private: state.app_config.metadata_allow_private_ips,
+        }
+        .spawn();
+    }
+    let startup = Startup {
+        app: App {
+            name: APP_NAME.to_owned(),
+        },
+    };
+    state.event_logger.log_event("startup", &startup).await?;
+
     // listen
     app.listen("0.0.0.0:8080").await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_types::{Method, Url};
+
+    fn test_config() -> AppConfig {
+        let mut app_config = AppConfig::from_iter(&[APP_NAME]);
+        app_config.redis_urls_client_conn = fake_redis::start();
+        app_config.event_log_folder = tempfile::tempdir().unwrap().into_path();
+        app_config
+    }
+
+    async fn test_app() -> tide::Server<AppState> {
+        new_app(test_config()).await.unwrap()
+    }
+
+    async fn create(
+        app: &tide::Server<AppState>,
+        body: &str,
+        idempotency_key: Option<&str>,
+    ) -> (StatusCode, String) {
+        let mut req =
+            http_types::Request::new(Method::Post, Url::parse("http://localhost:8080/").unwrap());
+        req.set_body(body);
+        if let Some(key) = idempotency_key {
+            req.insert_header("idempotency-key", key);
+        }
+        let mut res: http_types::Response = app.respond(req).await.unwrap();
+        (res.status(), res.body_string().await.unwrap())
+    }
+
+    /// A new api key of `owner`, stored straight away.
+    async fn api_key(app: &tide::Server<AppState>, owner: &str, scopes: &[Scope]) -> String {
+        let (api_key, key) = ApiKey::generate("test", owner, scopes.iter().cloned().collect());
+        app.state()
+            .api_key_dao
+            .create_api_key(&api_key)
+            .await
+            .unwrap();
+        key
+    }
+
+    async fn send(
+        app: &tide::Server<AppState>,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<&str>,
+    ) -> (StatusCode, String) {
+        let url = Url::parse("http://localhost:8080")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        let mut req = http_types::Request::new(method, url);
+        if let Some(token) = token {
+            req.insert_header("authorization", format!("Bearer {}", token));
+        }
+        if let Some(body) = body {
+            req.set_body(body);
+        }
+        let mut res: http_types::Response = app.respond(req).await.unwrap();
+        (res.status(), res.body_string().await.unwrap())
+    }
+
+    #[async_std::test]
+    async fn aliases() {
+        let app = test_app().await;
+        let (status, body) = create(
+            &app,
+            r#"{"long_url":"https://example.com/a","alias":"caf%C3%A9"}"#,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert!(body.contains(r#""id":"café""#), "{}", body);
+
+        let (status, _) = create(
+            &app,
+            r#"{"long_url":"https://example.com/b","alias":"café"}"#,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::Conflict);
+
+        for alias in &["links", "id_counter", "api_key_last_used", "sale\\u200b"] {
+            let body = format!(
+                r#"{{"long_url":"https://example.com/c","alias":"{}"}}"#,
+                alias
+            );
+            let (status, _) = create(&app, &body, None).await;
+            assert_eq!(status, StatusCode::UnprocessableEntity, "{}", alias);
+        }
+    }
+
+    #[async_std::test]
+    async fn alias_conflicts_release_the_idempotency_key() {
+        let app = test_app().await;
+        let taken = r#"{"long_url":"https://example.com/a","alias":"sale"}"#;
+        assert_eq!(create(&app, taken, None).await.0, StatusCode::Ok);
+
+        let (status, _) = create(&app, taken, Some("k1")).await;
+        assert_eq!(status, StatusCode::Conflict);
+        // the key is free again for the request with another alias
+        let other = r#"{"long_url":"https://example.com/a","alias":"sale2"}"#;
+        let (status, body) = create(&app, other, Some("k1")).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert_eq!(create(&app, other, Some("k1")).await.1, body);
+    }
+
+    #[async_std::test]
+    async fn failures_release_the_idempotency_key() {
+        let policy_path = tempfile::tempdir().unwrap().into_path().join("policy");
+        std::fs::write(&policy_path, "deny example.com\n").unwrap();
+        let mut app_config = test_config();
+        app_config.url_policy_path = Some(policy_path.to_owned());
+        let app = new_app(app_config).await.unwrap();
+
+        let body = r#"{"long_url":"https://example.com/a"}"#;
+        assert_eq!(
+            create(&app, body, Some("k1")).await.0,
+            StatusCode::Forbidden
+        );
+        std::fs::write(&policy_path, "").unwrap();
+        app.state().url_policy.reload().unwrap();
+        let (status, created) = create(&app, body, Some("k1")).await;
+        assert_eq!(status, StatusCode::Ok, "{}", created);
+        assert_eq!(create(&app, body, Some("k1")).await.1, created);
+    }
+
+    #[async_std::test]
+    async fn create_limit_is_only_for_creating() {
+        let mut app_config = test_config();
+        app_config.rate_limit_create = "1:1".parse().unwrap();
+        let app = new_app(app_config).await.unwrap();
+        for _ in 0..3 {
+            let req = http_types::Request::new(
+                Method::Get,
+                Url::parse("http://localhost:8080/").unwrap(),
+            );
+            let res: http_types::Response = app.respond(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::PermanentRedirect);
+        }
+        let body = r#"{"long_url":"https://example.com/a"}"#;
+        assert_eq!(create(&app, body, None).await.0, StatusCode::Ok);
+        assert_eq!(
+            create(&app, body, None).await.0,
+            StatusCode::TooManyRequests
+        );
+    }
+
+    #[async_std::test]
+    async fn private_routes_need_an_admin() {
+        let app = test_app().await;
+        for (method, path) in &[
+            (Method::Post, "/private/url-policy/reload"),
+            (Method::Get, "/private/ids/aaaaaaaa"),
+        ] {
+            let url = Url::parse("http://localhost:8080")
+                .unwrap()
+                .join(path)
+                .unwrap();
+            let req = http_types::Request::new(*method, url);
+            let res: http_types::Response = app.respond(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::Unauthorized, "{}", path);
+        }
+    }
+
+    #[async_std::test]
+    async fn changes_resolve_like_creates() {
+        let app = test_app().await;
+        let key = api_key(
+            &app,
+            "a@example.com",
+            &[Scope::LinksRead, Scope::LinksWrite],
+        )
+        .await;
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            "/",
+            Some(&key),
+            Some(r#"{"long_url":"https://example.com/a"}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let path = format!("/api/links/{}", id);
+        let to_itself = format!(r#"{{"long_url":"http://localhost:8080/{}"}}"#, id);
+        let (status, body) = send(&app, Method::Put, &path, Some(&key), Some(&to_itself)).await;
+        assert_eq!(status, StatusCode::UnprocessableEntity, "{}", body);
+        let to_missing = r#"{"long_url":"http://localhost:8080/missing"}"#;
+        let (status, _) = send(&app, Method::Put, &path, Some(&key), Some(to_missing)).await;
+        assert_eq!(status, StatusCode::UnprocessableEntity);
+
+        let rollback = format!("/api/links/{}/rollback", id);
+        let (status, body) = send(
+            &app,
+            Method::Post,
+            &rollback,
+            Some(&key),
+            Some(r#"{"version":1}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let (status, _) = send(
+            &app,
+            Method::Post,
+            &rollback,
+            Some(&key),
+            Some(r#"{"version":9}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UnprocessableEntity);
+    }
+
+    #[async_std::test]
+    async fn dedupe_keeps_details() {
+        let app = test_app().await;
+        let key = api_key(&app, "a@example.com", &[Scope::LinksWrite]).await;
+        let shorten = |body: &'static str| send(&app, Method::Post, "/", Some(&key), Some(body));
+        let (status, body) = shorten(r#"{"long_url":"https://example.com/a","title":"A"}"#).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+
+        let (status, body) = shorten(r#"{"long_url":"https://example.com/a","dedupe":true}"#).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert!(body.contains(r#""existing":true"#), "{}", body);
+        let (status, _) =
+            shorten(r#"{"long_url":"https://example.com/a","dedupe":true,"title":"B"}"#).await;
+        assert_eq!(status, StatusCode::Conflict);
+    }
+
+    #[async_std::test]
+    async fn conversions_are_posted() {
+        let app = test_app().await;
+        let (status, body) = create(&app, r#"{"long_url":"https://example.com/a"}"#, None).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let path = format!("/{}/convert", id);
+        let (status, _) = send(&app, Method::Get, &path, None, None).await;
+        assert_eq!(status, StatusCode::MethodNotAllowed);
+        let (status, _) = send(&app, Method::Post, &path, None, None).await;
+        assert_eq!(status, StatusCode::NoContent);
+    }
+
+    #[async_std::test]
+    async fn extra_domain_links_only_on_their_host() {
+        let mut app_config = test_config();
+        app_config.extra_base_hosts = vec!["go.example.com".to_owned()];
+        let app = new_app(app_config).await.unwrap();
+        let (status, body) = create(
+            &app,
+            r#"{"long_url":"https://example.com/a","domain":"go.example.com","password":"secret"}"#,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+
+        let on = |base: &str, method: Method, path: String| {
+            let url = Url::parse(base).unwrap().join(&path).unwrap();
+            let mut req = http_types::Request::new(method, url);
+            if method == Method::Post {
+                req.set_body("password=wrong");
+                req.set_content_type(http_types::mime::FORM);
+            }
+            let app = app.clone();
+            async move {
+                let res: http_types::Response = app.respond(req).await.unwrap();
+                res.status()
+            }
+        };
+        for base in &["http://localhost:8080", "http://go.example.com"] {
+            let expected = |status| match *base {
+                "http://go.example.com" => status,
+                _ => StatusCode::NotFound,
+            };
+            let qr = on(base, Method::Get, format!("/{}/qr", id)).await;
+            assert_eq!(qr, expected(StatusCode::Ok), "{}", base);
+            let unlock = on(base, Method::Post, format!("/{}", id)).await;
+            assert_eq!(unlock, expected(StatusCode::Unauthorized), "{}", base);
+        }
+    }
+
+    #[async_std::test]
+    async fn anonymous_links_only_for_admins() {
+        let mut app_config = test_config();
+        app_config.admin_emails = vec!["admin@example.com".to_owned()];
+        let app = new_app(app_config).await.unwrap();
+        let (status, body) = create(&app, r#"{"long_url":"https://example.com/a"}"#, None).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let path = format!("/api/links/{}", id);
+
+        let user = api_key(&app, "a@example.com", &[Scope::LinksRead]).await;
+        let (status, _) = send(&app, Method::Get, &path, Some(&user), None).await;
+        assert_eq!(status, StatusCode::NotFound);
+        let admin_scopes = [Scope::LinksRead, Scope::Admin];
+        let not_admin = api_key(&app, "a@example.com", &admin_scopes).await;
+        let (status, _) = send(&app, Method::Get, &path, Some(&not_admin), None).await;
+        assert_eq!(status, StatusCode::NotFound);
+        let admin = api_key(&app, "admin@example.com", &admin_scopes).await;
+        let (status, body) = send(&app, Method::Get, &path, Some(&admin), None).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+    }
+
+    #[async_std::test]
+    async fn details_are_not_versions() {
+        let app = test_app().await;
+        let key = api_key(
+            &app,
+            "a@example.com",
+            &[Scope::LinksRead, Scope::LinksWrite],
+        )
+        .await;
+        let body = r#"{"long_url":"https://example.com/a"}"#;
+        let (status, body) = send(&app, Method::Post, "/", Some(&key), Some(body)).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let path = format!("/api/links/{}", id);
+        for change in &[
+            r#"{"title":"A","tags":["sale"]}"#,
+            r#"{"long_url":"https://example.com/a","notes":"same destination"}"#,
+        ] {
+            let (status, body) = send(&app, Method::Put, &path, Some(&key), Some(change)).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+        }
+        let versions = format!("{}/versions", path);
+        let (status, body) = send(&app, Method::Get, &versions, Some(&key), None).await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let versions = serde_json::from_str::<serde_json::Value>(&body).unwrap()["versions"]
+            .as_array()
+            .unwrap()
+            .len();
+        assert_eq!(versions, 1, "{}", body);
+    }
+
+    #[test]
+    fn link_data_urls_are_unicode() {
+        let mut data = MicroUrlData::new("https://xn--caf-dma.example/a");
+        data.destinations = vec![Destination {
+            long_url: "https://xn--caf-dma.example/b".to_owned(),
+            weight: 1,
+        }];
+        data.schedule = vec![ScheduledDestination {
+            from: "2020-06-01T00:00:00Z".parse().unwrap(),
+            long_url: "https://xn--caf-dma.example/c".to_owned(),
+        }];
+        data.app_link = Some(AppLink {
+            ios_url: None,
+            ios_store_url: Some("https://xn--caf-dma.example/d".to_owned()),
+            android_url: None,
+            android_store_url: None,
+        });
+        let link = LinkData::new(data, chrono::Utc::now());
+        assert_eq!(link.long_url, "https://café.example/a");
+        assert_eq!(link.destinations[0].long_url, "https://café.example/b");
+        assert_eq!(link.schedule[0].long_url, "https://café.example/c");
+        assert_eq!(link.current_long_url, "https://café.example/c");
+        let ios_store_url = link.app_link.unwrap().ios_store_url;
+        assert_eq!(ios_store_url.as_deref(), Some("https://café.example/d"));
+    }
+
+    #[async_std::test]
+    async fn other_keys_are_not_micro_urls() {
+        let app = test_app().await;
+        let (status, body) = create(
+            &app,
+            r#"{"long_url":"https://example.com/secret","password":"secret"}"#,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let now = chrono::Utc::now();
+        let health = LinkHealth {
+            long_url: "https://example.com/secret".to_owned(),
+            checked_at: now,
+            healthy: true,
+            status: Some(200),
+            error: None,
+            failures: 0,
+            next_check_at: now,
+        };
+        app.state()
+            .health_dao
+            .set_health(&id, &health)
+            .await
+            .unwrap();
+
+        let health_path = format!("/health:{}", id);
+        for path in &[
+            health_path.as_str(),
+            "/links",
+            "/id_counter",
+            "/api_key_last_used",
+        ] {
+            let (status, body) = send(&app, Method::Get, path, None, None).await;
+            assert_eq!(status, StatusCode::NotFound, "{} {}", path, body);
+        }
+    }
+
+    #[async_std::test]
+    async fn api_keys_need_a_verified_google_login() {
+        let mut app_config = test_config();
+        app_config.google_client_id = Some("us".to_owned());
+        app_config.admin_emails = vec!["admin@example.com".to_owned()];
+        let app = new_app(app_config).await.unwrap();
+        google_auth::fake_token("keys-unverified", "admin@example.com", false, "us");
+        google_auth::fake_token("keys-other-client", "admin@example.com", true, "them");
+        google_auth::fake_token("keys-user", "a@example.com", true, "us");
+        google_auth::fake_token("keys-admin", "admin@example.com", true, "us");
+
+        let create_key = |token: &'static str, scopes: &'static str| {
+            let body = format!(r#"{{"name":"ci","scopes":[{}]}}"#, scopes);
+            let app = &app;
+            async move {
+                send(app, Method::Post, "/api/keys", Some(token), Some(&body))
+                    .await
+                    .0
+            }
+        };
+        let links = r#""links:read""#;
+        let admin = r#""links:read","admin""#;
+        assert_eq!(
+            create_key("keys-unverified", links).await,
+            StatusCode::Unauthorized
+        );
+        assert_eq!(
+            create_key("keys-other-client", links).await,
+            StatusCode::Unauthorized
+        );
+        assert_eq!(create_key("keys-user", links).await, StatusCode::Ok);
+        assert_eq!(create_key("keys-user", admin).await, StatusCode::Forbidden);
+        assert_eq!(create_key("keys-admin", admin).await, StatusCode::Ok);
+    }
+
+    #[async_std::test]
+    async fn guesses_on_a_link_do_not_lock_out_the_password() {
+        let mut app_config = test_config();
+        app_config.password_max_attempts_per_link = 1;
+        app_config.password_link_delay_ms = 0;
+        let app = new_app(app_config).await.unwrap();
+        let (status, body) = create(
+            &app,
+            r#"{"long_url":"https://example.com/a","password":"secret"}"#,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        let id = serde_json::from_str::<serde_json::Value>(&body).unwrap()["data"]["id"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let unlock = |ip: &'static str, password: &'static str| {
+            let url = Url::parse("http://localhost:8080")
+                .unwrap()
+                .join(&id)
+                .unwrap();
+            let mut req = http_types::Request::new(Method::Post, url);
+            req.insert_header("x-forwarded-for", ip);
+            req.set_body(format!("password={}", password));
+            req.set_content_type(http_types::mime::FORM);
+            let app = &app;
+            async move {
+                let res: http_types::Response = app.respond(req).await.unwrap();
+                res.status()
+            }
+        };
+        for ip in &["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+            assert_eq!(unlock(ip, "guess").await, StatusCode::Unauthorized);
+        }
+        assert_eq!(unlock("10.0.0.4", "secret").await, StatusCode::SeeOther);
+    }
+
+    #[async_std::test]
+    async fn links_not_live_are_not_scraped() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sale", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                let body = "<html><head><title>Sale</title></head></html>";
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+        let mut app_config = test_config();
+        app_config.metadata_allow_private_ips = true;
+        let app = new_app(app_config).await.unwrap();
+        let later = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+        let mut ids = vec![];
+        for body in &[
+            format!(r#"{{"long_url":"{}","not_before":"{}"}}"#, url, later),
+            format!(r#"{{"long_url":"{}"}}"#, url),
+        ] {
+            let (status, body) = create(&app, body, None).await;
+            assert_eq!(status, StatusCode::Ok, "{}", body);
+            let created = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+            ids.push(created["data"]["id"].as_str().unwrap().to_owned());
+        }
+
+        let metadata_dao = &app.state().metadata_dao;
+        for _ in 0..50 {
+            if metadata_dao.get_metadata(&ids[1]).await.unwrap().is_some() {
+                break;
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert!(metadata_dao.get_metadata(&ids[1]).await.unwrap().is_some());
+        assert!(metadata_dao.get_metadata(&ids[0]).await.unwrap().is_none());
+    }
+}
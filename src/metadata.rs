@@ -0,0 +1,393 @@
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use fehler::*;
+
+/// Longest title or description kept, in chars.
+const MAX_TEXT_LEN: usize = 500;
+
+/// What a destination page says about itself.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct PageMetadata {
+    pub long_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_description: Option<String>,
+    /// absolute http(s) url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub og_image: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl PageMetadata {
+    pub fn new(long_url: &str, fetched_at: DateTime<Utc>) -> PageMetadata {
+        PageMetadata {
+            long_url: long_url.to_owned(),
+            title: None,
+            og_title: None,
+            og_description: None,
+            og_image: None,
+            fetched_at,
+        }
+    }
+
+    /// The og title, or the page title when there is none.
+    pub fn display_title(&self) -> Option<&str> {
+        self.og_title.as_deref().or(self.title.as_deref())
+    }
+}
+
+/// Whether an address is on the public internet, so that scraping can not be pointed at the
+/// metadata service of the cloud provider or anything else on the internal network.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || a == 0
+                // shared address space, carrier grade nat
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    // unique local
+                    || (first & 0xfe00) == 0xfc00
+                    // link local
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves only to public addresses, for every hop of the redirects too.
+pub struct PublicResolver;
+
+impl ureq::Resolver for PublicResolver {
+    fn resolve(&self, netloc: &str) -> std::io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = netloc
+            .to_socket_addrs()?
+            .filter(|a| is_public(a.ip()))
+            .collect();
+        if addrs.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("[{}] is not a public address", netloc),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetadataFetcher {
+    pub timeout: Duration,
+    /// only this much of the page is read
+    pub max_bytes: u64,
+    /// for local development and tests only
+    pub allow_private: bool,
+}
+
+impl MetadataFetcher {
+    /// Fetches and parses `long_url`. Blocks.
+    #[throws(anyhow::Error)]
+    pub fn fetch(&self, long_url: &str) -> PageMetadata {
+        let mut agent = ureq::agent();
+        if !self.allow_private {
+            agent.set_resolver(PublicResolver);
+        }
+        let response = agent
+            .get(long_url)
+            .timeout(self.timeout)
+            .redirects(5)
+            .set("accept", "text/html")
+            .call();
+        if let Some(e) = response.synthetic_error() {
+            throw!(anyhow::anyhow!("unable to fetch [{}], {}", long_url, e));
+        }
+        if !response.ok() {
+            throw!(anyhow::anyhow!(
+                "unable to fetch [{}], status {}",
+                long_url,
+                response.status()
+            ));
+        }
+        if !response.content_type().contains("html") {
+            throw!(anyhow::anyhow!(
+                "[{}] is not html but {}",
+                long_url,
+                response.content_type()
+            ));
+        }
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(self.max_bytes)
+            .read_to_end(&mut body)?;
+        parse_metadata(long_url, &String::from_utf8_lossy(&body), Utc::now())
+    }
+}
+
+pub fn parse_metadata(long_url: &str, html: &str, now: DateTime<Utc>) -> PageMetadata {
+    // ascii lowercasing keeps the byte offsets the same
+    let lower = html.to_ascii_lowercase();
+    let mut metadata = PageMetadata::new(long_url, now);
+
+    if let Some(start) = lower.find("<title") {
+        if let Some(open_end) = lower[start..].find('>').map(|i| start + i + 1) {
+            if let Some(close) = lower[open_end..].find("</title").map(|i| open_end + i) {
+                metadata.title = clean_text(&decode_entities(&html[open_end..close]));
+            }
+        }
+    }
+
+    let mut from = 0;
+    while let Some(start) = lower[from..].find("<meta").map(|i| from + i) {
+        let end = lower[start..].find('>').map_or(lower.len(), |i| start + i);
+        let attributes = parse_attributes(&html[start + "<meta".len()..end]);
+        from = end;
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let content = match attribute("content") {
+            Some(c) => c,
+            None => continue,
+        };
+        let property = attribute("property").or_else(|| attribute("name"));
+        match property.map(|p| p.to_ascii_lowercase()).as_deref() {
+            Some("og:title") if metadata.og_title.is_none() => {
+                metadata.og_title = clean_text(content)
+            }
+            Some("og:description") if metadata.og_description.is_none() => {
+                metadata.og_description = clean_text(content)
+            }
+            Some("og:image") if metadata.og_image.is_none() => {
+                metadata.og_image = absolute_url(long_url, content.trim())
+            }
+            _ => (),
+        }
+    }
+    metadata
+}
+
+/// Lowercased names and entity decoded values of the attributes in the inside of a tag.
+fn parse_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut chars = tag.char_indices().peekable();
+    loop {
+        while chars
+            .next_if(|(_, c)| c.is_whitespace() || *c == '/')
+            .is_some()
+        {}
+        let name_start = match chars.peek() {
+            Some((i, _)) => *i,
+            None => break,
+        };
+        let mut name_end = tag.len();
+        while let Some((i, c)) = chars.peek() {
+            if c.is_whitespace() || *c == '=' || *c == '/' {
+                name_end = *i;
+                break;
+            }
+            chars.next();
+        }
+        let name = tag[name_start..name_end].to_ascii_lowercase();
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        if chars.next_if(|(_, c)| *c == '=').is_none() {
+            attributes.push((name, String::new()));
+            continue;
+        }
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        let value = match chars.peek() {
+            Some((i, q)) if *q == '"' || *q == '\'' => {
+                let (start, quote) = (*i + 1, *q);
+                chars.next();
+                let mut end = tag.len();
+                for (i, c) in chars.by_ref() {
+                    if c == quote {
+                        end = i;
+                        break;
+                    }
+                }
+                &tag[start..end]
+            }
+            Some((start, _)) => {
+                let start = *start;
+                let mut end = tag.len();
+                while let Some((i, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        end = *i;
+                        break;
+                    }
+                    chars.next();
+                }
+                &tag[start..end]
+            }
+            None => "",
+        };
+        attributes.push((name, decode_entities(value)));
+    }
+    attributes
+}
+
+fn decode_entities(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Collapses whitespace and caps the length, none when nothing is left.
+fn clean_text(s: &str) -> Option<String> {
+    let text = s.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.chars().take(MAX_TEXT_LEN).collect())
+    }
+}
+
+fn absolute_url(base: &str, url: &str) -> Option<String> {
+    let url = http_types::Url::parse(base).ok()?.join(url).ok()?;
+    match url.scheme() {
+        "http" | "https" => Some(url.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    const PAGE: &str = r#"<!doctype html><html><head>
+<TITLE> Spring   sale &amp; more </TITLE>
+<meta property="og:title" content="The Spring Sale">
+<meta name=og:description content='Up to 50% off, "today"'/>
+<meta property="og:image" content="/img/sale.png">
+<meta property="og:image" content="/img/other.png">
+</head><body></body></html>"#;
+
+    #[test]
+    fn parse() {
+        let now = Utc::now();
+        let metadata = parse_metadata("https://example.com/sale/", PAGE, now);
+        assert_eq!(metadata.title.as_deref(), Some("Spring sale & more"));
+        assert_eq!(metadata.og_title.as_deref(), Some("The Spring Sale"));
+        assert_eq!(
+            metadata.og_description.as_deref(),
+            Some("Up to 50% off, \"today\"")
+        );
+        assert_eq!(
+            metadata.og_image.as_deref(),
+            Some("https://example.com/img/sale.png")
+        );
+        assert_eq!(metadata.display_title(), Some("The Spring Sale"));
+
+        let empty = parse_metadata("https://example.com/", "<meta content=\"x\"><title>", now);
+        assert_eq!(empty.title, None);
+        assert_eq!(empty.og_title, None);
+
+        let script = parse_metadata(
+            "https://example.com/",
+            "<meta property=\"og:image\" content=\"javascript:alert(1)\">",
+            now,
+        );
+        assert_eq!(script.og_image, None);
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &["8.8.8.8", "100.128.0.1", "2001:4860:4860::8888"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    /// Serves `body` as html once per connection.
+    fn stub(body: &'static str, requests: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sale/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\ncontent-type: text/html; charset=utf-8\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn fetch() {
+        let fetcher = MetadataFetcher {
+            timeout: Duration::from_secs(5),
+            max_bytes: 64 * 1024,
+            allow_private: true,
+        };
+        let url = stub(PAGE, 1);
+        let metadata = fetcher.fetch(&url).unwrap();
+        assert_eq!(metadata.og_title.as_deref(), Some("The Spring Sale"));
+        assert_eq!(metadata.long_url, url);
+
+        // only the start of the page is read
+        let url = stub(PAGE, 1);
+        let small = MetadataFetcher {
+            max_bytes: 80,
+            ..fetcher.clone()
+        };
+        let metadata = small.fetch(&url).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Spring sale & more"));
+        assert_eq!(metadata.og_title, None);
+
+        // the stub is on a loopback address
+        let url = stub(PAGE, 1);
+        let public_only = MetadataFetcher {
+            allow_private: false,
+            ..fetcher
+        };
+        assert!(public_only.fetch(&url).is_err());
+    }
+}
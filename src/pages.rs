@@ -0,0 +1,372 @@
+use chrono::{DateTime, Utc};
+use tide::http::mime;
+use tide::{Response, StatusCode};
+
+use crate::idn::{homograph_warning, unicode_host, unicode_url};
+use crate::metadata::PageMetadata;
+use crate::utils::{encode_id, escape_html, js_string, url_host};
+
+fn page(title: &str, head: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>{}</title>
+{}
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape_html(title),
+        head,
+        body
+    )
+}
+
+pub fn html_response(status: StatusCode, html: String) -> Response {
+    Response::builder(status)
+        .body(html)
+        .content_type(mime::HTML)
+        .header("cache-control", "no-store")
+        .build()
+}
+
+/// Tries to open the app, and if the page is still visible after a moment goes to the fallback.
+pub fn app_interstitial(app_url: &str, fallback_url: &str) -> String {
+    let script = format!(
+        r#"<script>
+var fallback = {fallback};
+setTimeout(function () {{ if (!document.hidden) {{ window.location.replace(fallback); }} }}, 1500);
+window.location.replace({app});
+</script>"#,
+        app = js_string(app_url),
+        fallback = js_string(fallback_url),
+    );
+    let body = format!(
+        r#"<p>Opening the app&hellip;</p>
+<p><a href="{app}">Open in app</a> or <a href="{fallback}">continue</a>.</p>
+{script}"#,
+        app = escape_html(app_url),
+        fallback = escape_html(fallback_url),
+        script = script,
+    );
+    page("Opening the app", "", &body)
+}
+
+pub fn password_form(id: &str, failed: bool) -> String {
+    let error = if failed {
+        "<p role=\"alert\">That password is not right, try again.</p>\n"
+    } else {
+        ""
+    };
+    let body = format!(
+        r#"<p>This link is password protected.</p>
+{error}<form method="post" action="/{id}">
+<label>Password <input type="password" name="password" autofocus required></label>
+<button type="submit">Continue</button>
+</form>"#,
+        error = error,
+        id = escape_html(&encode_id(id)),
+    );
+    page("Password required", "", &body)
+}
+
+pub fn too_many_attempts() -> String {
+    page(
+        "Too many attempts",
+        "",
+        "<p>Too many wrong passwords, try again later.</p>",
+    )
+}
+
+pub fn coming_soon(not_before: Option<DateTime<Utc>>) -> String {
+    let when = match not_before {
+        Some(nb) => format!(
+            "<p>Check back after <time datetime=\"{}\">{}</time>.</p>",
+            nb.to_rfc3339(),
+            nb.format("%Y-%m-%d %H:%M UTC")
+        ),
+        None => "".to_owned(),
+    };
+    page(
+        "Coming soon",
+        "",
+        &format!("<p>This link is not active yet.</p>\n{}", when),
+    )
+}
+
+pub fn link_disabled() -> String {
+    page(
+        "Link disabled",
+        "",
+        "<p>This link has been disabled because its destination is not allowed.</p>",
+    )
+}
+
+/// Shown for a missing id that is close to ones that exist, never followed automatically since
+/// ids that only differ in case are different links.
+pub fn did_you_mean(micro_urls: &[String]) -> String {
+    let links: Vec<String> = micro_urls
+        .iter()
+        .map(|u| format!("<li><a href=\"{0}\">{0}</a></li>", escape_html(u)))
+        .collect();
+    page(
+        "Link not found",
+        "",
+        &format!(
+            "<p>This link does not exist. Did you mean</p>\n<ul>\n{}\n</ul>",
+            links.join("\n")
+        ),
+    )
+}
+
+/// What link preview bots are shown instead of the redirect.
+pub struct Unfurl<'a> {
+    pub micro_url: &'a str,
+    pub long_url: &'a str,
+    pub title: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub image: Option<&'a str>,
+}
+
+pub fn unfurl(unfurl: &Unfurl) -> String {
+    let title = unfurl.title.unwrap_or(unfurl.long_url);
+    let mut meta = vec![
+        ("og:type", "website"),
+        ("og:url", unfurl.micro_url),
+        ("og:title", title),
+        ("twitter:title", title),
+    ];
+    if let Some(description) = unfurl.description {
+        meta.push(("og:description", description));
+        meta.push(("twitter:description", description));
+    }
+    match unfurl.image {
+        Some(image) => {
+            meta.push(("og:image", image));
+            meta.push(("twitter:image", image));
+            meta.push(("twitter:card", "summary_large_image"));
+        }
+        None => meta.push(("twitter:card", "summary")),
+    }
+    let head = meta
+        .iter()
+        .map(|(property, content)| {
+            // twitter reads `name`, everyone else `property`
+            let attribute = if property.starts_with("twitter:") {
+                "name"
+            } else {
+                "property"
+            };
+            format!(
+                "<meta {}=\"{}\" content=\"{}\">",
+                attribute,
+                property,
+                escape_html(content)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let body = format!(
+        "<p><a href=\"{}\">{}</a></p>",
+        escape_html(unfurl.long_url),
+        escape_html(title)
+    );
+    page(title, &head, &body)
+}
+
+/// What the preview page shows about a micro url.
+pub struct Preview<'a> {
+    pub micro_url: &'a str,
+    pub long_urls: Vec<&'a str>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub password_protected: bool,
+    pub not_before: Option<DateTime<Utc>>,
+    pub metadata: Option<&'a PageMetadata>,
+}
+
+pub fn preview(preview: &Preview) -> String {
+    let mut body = format!(
+        "<h1>Where does <code>{}</code> go?</h1>\n",
+        escape_html(preview.micro_url)
+    );
+    if preview.password_protected {
+        body.push_str("<p>This link is password protected, its destination is hidden.</p>\n");
+    } else if preview.not_before.is_some() {
+        body.push_str("<p>This link is not active yet, its destination is hidden.</p>\n");
+    } else {
+        if preview.long_urls.len() > 1 {
+            body.push_str("<p>This link splits visitors between these destinations.</p>\n");
+        }
+        body.push_str("<dl>\n");
+        for long_url in preview.long_urls.iter() {
+            let ascii_domain = url_host(long_url).unwrap_or_default();
+            let domain = unicode_host(long_url).unwrap_or_default();
+            // the punycode too, so look-alike unicode can be told apart
+            let punycode = if domain != ascii_domain {
+                format!(" ({})", escape_html(&ascii_domain))
+            } else {
+                "".to_owned()
+            };
+            body.push_str(&format!(
+                "<dt>Domain</dt><dd><strong>{}</strong>{}</dd>\n<dt>Destination</dt><dd><code>{}</code></dd>\n",
+                escape_html(&domain),
+                punycode,
+                escape_html(&unicode_url(long_url))
+            ));
+            if let Some(warning) = homograph_warning(long_url) {
+                body.push_str(&format!(
+                    "<p role=\"alert\">Careful, {}.</p>\n",
+                    escape_html(&warning)
+                ));
+            }
+        }
+        body.push_str("</dl>\n");
+        if let Some(metadata) = preview.metadata {
+            if let Some(title) = metadata.display_title() {
+                body.push_str(&format!("<h2>{}</h2>\n", escape_html(title)));
+            }
+            if let Some(ref description) = metadata.og_description {
+                body.push_str(&format!("<p>{}</p>\n", escape_html(description)));
+            }
+            if let Some(ref image) = metadata.og_image {
+                body.push_str(&format!(
+                    "<img src=\"{}\" alt=\"\" referrerpolicy=\"no-referrer\" style=\"max-width: 100%\">\n",
+                    escape_html(image)
+                ));
+            }
+        }
+    }
+    if let Some(created_at) = preview.created_at {
+        body.push_str(&format!(
+            "<p>Created <time datetime=\"{}\">{}</time>.</p>\n",
+            created_at.to_rfc3339(),
+            created_at.format("%Y-%m-%d")
+        ));
+    }
+    if let Some(not_before) = preview.not_before {
+        body.push_str(&format!(
+            "<p>Not active until <time datetime=\"{}\">{}</time>.</p>\n",
+            not_before.to_rfc3339(),
+            not_before.format("%Y-%m-%d %H:%M UTC")
+        ));
+    }
+    body.push_str(&format!(
+        "<p><a href=\"{}\" rel=\"noopener noreferrer\">Continue</a></p>",
+        escape_html(preview.micro_url)
+    ));
+    page("Link preview", "", &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_escapes() {
+        let html = preview(&Preview {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_urls: vec!["https://example.com/?q=\"><script>alert(1)</script>"],
+            created_at: Some("2020-06-01T00:00:00Z".parse().unwrap()),
+            password_protected: false,
+            not_before: None,
+            metadata: None,
+        });
+        assert!(!html.contains("<script>alert"));
+        assert!(
+            html.contains("https://example.com/?q=&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;")
+        );
+        assert!(html.contains("<strong>example.com</strong>"));
+        assert!(html.contains("2020-06-01"));
+        assert!(html.contains("href=\"https://utrakr.app/abcdefgh\""));
+    }
+
+    #[test]
+    fn did_you_mean_links() {
+        let html = did_you_mean(&["https://utrakr.app/abcdefgh".to_owned()]);
+        assert!(html.contains("<a href=\"https://utrakr.app/abcdefgh\">"));
+    }
+
+    #[test]
+    fn preview_idn() {
+        let html = preview(&Preview {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_urls: vec!["https://xn--pypal-4ve.com/login"],
+            created_at: None,
+            password_protected: false,
+            not_before: None,
+            metadata: None,
+        });
+        assert!(html.contains("<strong>p\u{430}ypal.com</strong> (xn--pypal-4ve.com)"));
+        assert!(html.contains("<code>https://p\u{430}ypal.com/login</code>"));
+        assert!(html.contains("role=\"alert\""));
+    }
+
+    #[test]
+    fn unfurl_tags() {
+        let html = unfurl(&Unfurl {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_url: "https://example.com/sale",
+            title: Some("50% \"off\""),
+            description: None,
+            image: Some("https://example.com/a.png"),
+        });
+        assert!(html.contains("<meta property=\"og:title\" content=\"50% &quot;off&quot;\">"));
+        assert!(html.contains("<meta name=\"twitter:card\" content=\"summary_large_image\">"));
+        assert!(html.contains("<meta property=\"og:url\" content=\"https://utrakr.app/abcdefgh\">"));
+        assert!(!html.contains("og:description"));
+    }
+
+    #[test]
+    fn preview_hides_protected() {
+        let mut metadata = PageMetadata::new("https://example.com/secret", Utc::now());
+        metadata.og_title = Some("Secret plans".to_owned());
+        let html = preview(&Preview {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_urls: vec!["https://example.com/secret"],
+            created_at: None,
+            password_protected: true,
+            not_before: None,
+            metadata: Some(&metadata),
+        });
+        assert!(!html.contains("example.com"));
+        assert!(!html.contains("Secret plans"));
+
+        // nor before it goes live
+        let html = preview(&Preview {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_urls: vec!["https://example.com/secret"],
+            created_at: None,
+            password_protected: false,
+            not_before: Some(Utc::now() + chrono::Duration::days(1)),
+            metadata: Some(&metadata),
+        });
+        assert!(!html.contains("example.com"));
+        assert!(!html.contains("Secret plans"));
+        assert!(html.contains("Not active until"));
+    }
+
+    #[test]
+    fn preview_metadata() {
+        let mut metadata = PageMetadata::new("https://example.com/sale", Utc::now());
+        metadata.title = Some("Sale".to_owned());
+        metadata.og_description = Some("<b>50%</b> off".to_owned());
+        metadata.og_image = Some("https://example.com/a.png?x=1&y=2".to_owned());
+        let html = preview(&Preview {
+            micro_url: "https://utrakr.app/abcdefgh",
+            long_urls: vec!["https://example.com/sale"],
+            created_at: None,
+            password_protected: false,
+            not_before: None,
+            metadata: Some(&metadata),
+        });
+        assert!(html.contains("<h2>Sale</h2>"));
+        assert!(html.contains("&lt;b&gt;50%&lt;/b&gt; off"));
+        assert!(html.contains("src=\"https://example.com/a.png?x=1&amp;y=2\""));
+    }
+}
@@ -0,0 +1,99 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Ios,
+    Android,
+    Other,
+}
+
+impl Platform {
+    pub fn from_user_agent(user_agent: &str) -> Platform {
+        let ua = user_agent.to_ascii_lowercase();
+        // windows phone claims to be android too
+        if ua.contains("windows phone") {
+            Platform::Other
+        } else if ua.contains("android") {
+            Platform::Android
+        } else if ua.contains("iphone") || ua.contains("ipad") || ua.contains("ipod") {
+            Platform::Ios
+        } else {
+            Platform::Other
+        }
+    }
+}
+
+/// Bots that fetch links pasted in chats and posts to show a card for them.
+const LINK_PREVIEW_BOTS: &[&str] = &[
+    "slackbot",
+    "slack-imgproxy",
+    "twitterbot",
+    "facebookexternalhit",
+    "facebot",
+    "linkedinbot",
+    "discordbot",
+    "telegrambot",
+    "whatsapp",
+];
+
+pub fn is_link_preview_bot(user_agent: &str) -> bool {
+    let ua = user_agent.to_ascii_lowercase();
+    LINK_PREVIEW_BOTS.iter().any(|bot| ua.contains(bot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_preview_bots() {
+        assert!(is_link_preview_bot(
+            "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)"
+        ));
+        assert!(is_link_preview_bot("Twitterbot/1.0"));
+        assert!(is_link_preview_bot(
+            "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)"
+        ));
+        assert!(!is_link_preview_bot(
+            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 Safari/605.1.15"
+        ));
+    }
+
+    #[test]
+    fn ios() {
+        assert_eq!(
+            Platform::from_user_agent(
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 14_0 like Mac OS X) AppleWebKit/605.1.15"
+            ),
+            Platform::Ios
+        );
+        assert_eq!(
+            Platform::from_user_agent("Mozilla/5.0 (iPad; CPU OS 13_3 like Mac OS X)"),
+            Platform::Ios
+        );
+    }
+
+    #[test]
+    fn android() {
+        assert_eq!(
+            Platform::from_user_agent(
+                "Mozilla/5.0 (Linux; Android 10; SM-G973F) AppleWebKit/537.36 Mobile Safari"
+            ),
+            Platform::Android
+        );
+    }
+
+    #[test]
+    fn other() {
+        assert_eq!(
+            Platform::from_user_agent(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 Chrome/86.0"
+            ),
+            Platform::Other
+        );
+        assert_eq!(
+            Platform::from_user_agent("Mozilla/5.0 (Windows Phone 10.0; Android 6.0.1)"),
+            Platform::Other
+        );
+        assert_eq!(Platform::from_user_agent(""), Platform::Other);
+    }
+}
@@ -0,0 +1,412 @@
+use std::fmt::Write as _;
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::{anyhow, bail};
+use fehler::*;
+use qrcode::{Color, EcLevel, QrCode};
+
+const MAX_SIZE: u32 = 2048;
+const MAX_MARGIN: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+/// Query params for a qr code, everything is optional.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct QrRequest {
+    format: Option<QrFormat>,
+    /// width and height in pixels, rounded down to fit whole modules
+    size: Option<u32>,
+    /// quiet zone around the code, in modules
+    margin: Option<u32>,
+    /// error correction level, one of `L`, `M`, `Q`, `H`
+    ec: Option<String>,
+    /// hex colors, ie `000000`
+    dark: Option<String>,
+    light: Option<String>,
+    logo: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rgb(u8, u8, u8);
+
+impl Rgb {
+    #[throws(anyhow::Error)]
+    fn parse(s: &str) -> Rgb {
+        let s = s.trim_start_matches('#');
+        if s.len() != 6 || !s.is_ascii() {
+            bail!("color must be 6 hex digits, got [{}]", s);
+        }
+        let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16);
+        Rgb(channel(0)?, channel(2)?, channel(4)?)
+    }
+
+    fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+pub struct QrOptions {
+    pub format: QrFormat,
+    size: u32,
+    margin: u32,
+    ec: EcLevel,
+    dark: Rgb,
+    light: Rgb,
+    pub logo: bool,
+}
+
+impl QrOptions {
+    #[throws(anyhow::Error)]
+    pub fn from_request(request: &QrRequest) -> QrOptions {
+        let logo = request.logo.unwrap_or(false);
+        let ec = match request.ec.as_deref() {
+            Some("L") | Some("l") => EcLevel::L,
+            Some("M") | Some("m") => EcLevel::M,
+            Some("Q") | Some("q") => EcLevel::Q,
+            Some("H") | Some("h") => EcLevel::H,
+            Some(other) => bail!("unknown error correction level [{}]", other),
+            // the logo covers part of the code, so it needs the most redundancy
+            None if logo => EcLevel::H,
+            None => EcLevel::M,
+        };
+        let size = request.size.unwrap_or(512);
+        if size == 0 || size > MAX_SIZE {
+            bail!("size must be between 1 and {}", MAX_SIZE);
+        }
+        let margin = request.margin.unwrap_or(4);
+        if margin > MAX_MARGIN {
+            bail!("margin must be at most {}", MAX_MARGIN);
+        }
+        QrOptions {
+            format: request.format.unwrap_or(QrFormat::Png),
+            size,
+            margin,
+            ec,
+            dark: Rgb::parse(request.dark.as_deref().unwrap_or("000000"))?,
+            light: Rgb::parse(request.light.as_deref().unwrap_or("ffffff"))?,
+            logo,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self.format {
+            QrFormat::Png => "image/png",
+            QrFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// Image placed in the middle of qr codes, loaded once at startup.
+pub struct QrLogo {
+    png: Vec<u8>,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl QrLogo {
+    #[throws(anyhow::Error)]
+    pub fn load(path: &Path) -> QrLogo {
+        QrLogo::from_png(std::fs::read(path)?)?
+    }
+
+    #[throws(anyhow::Error)]
+    pub fn from_png(png: Vec<u8>) -> QrLogo {
+        let mut decoder = png::Decoder::new(Cursor::new(&png));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![
+            0;
+            reader
+                .output_buffer_size()
+                .ok_or_else(|| anyhow!("logo too large"))?
+        ];
+        let info = reader.next_frame(&mut buf)?;
+        let pixels = &buf[..info.buffer_size()];
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => pixels.to_vec(),
+            png::ColorType::Rgb => pixels
+                .chunks(3)
+                .flat_map(|p| vec![p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks(2)
+                .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => {
+                pixels.iter().flat_map(|g| vec![*g, *g, *g, 255]).collect()
+            }
+            other => bail!("unsupported logo color type {:?}", other),
+        };
+        QrLogo {
+            width: info.width,
+            height: info.height,
+            png,
+            rgba,
+        }
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * self.width + x) * 4) as usize;
+        [
+            self.rgba[i],
+            self.rgba[i + 1],
+            self.rgba[i + 2],
+            self.rgba[i + 3],
+        ]
+    }
+}
+
+/// Renders `data` as a qr code, with the logo in the middle when one is given.
+#[throws(anyhow::Error)]
+pub fn render(data: &str, options: &QrOptions, logo: Option<&QrLogo>) -> Vec<u8> {
+    let code = QrCode::with_error_correction_level(data, options.ec)?;
+    match options.format {
+        QrFormat::Png => render_png(&code, options, logo)?,
+        QrFormat::Svg => render_svg(&code, options, logo).into_bytes(),
+    }
+}
+
+fn is_dark(code: &QrCode, modules: u32, x: i64, y: i64) -> bool {
+    x >= 0
+        && y >= 0
+        && x < modules as i64
+        && y < modules as i64
+        && code[(x as usize, y as usize)] == Color::Dark
+}
+
+/// Side of the logo box, a fifth of the code keeps it well within what `H` can recover.
+fn logo_side(modules: u32) -> u32 {
+    modules / 5
+}
+
+#[throws(anyhow::Error)]
+fn render_png(code: &QrCode, options: &QrOptions, logo: Option<&QrLogo>) -> Vec<u8> {
+    let modules = code.width() as u32;
+    let total = modules + 2 * options.margin;
+    let scale = (options.size / total).max(1);
+    let px = total * scale;
+
+    let mut pixels = Vec::with_capacity((px * px * 3) as usize);
+    for y in 0..px {
+        for x in 0..px {
+            let mx = (x / scale) as i64 - options.margin as i64;
+            let my = (y / scale) as i64 - options.margin as i64;
+            let c = if is_dark(code, modules, mx, my) {
+                options.dark
+            } else {
+                options.light
+            };
+            pixels.extend_from_slice(&[c.0, c.1, c.2]);
+        }
+    }
+
+    if let Some(logo) = logo {
+        let side = logo_side(modules) * scale;
+        let start = (px - side) / 2;
+        // fit the logo in the box keeping its aspect ratio
+        let fit = side as f64 / logo.width.max(logo.height) as f64;
+        let (w, h) = (
+            (logo.width as f64 * fit) as u32,
+            (logo.height as f64 * fit) as u32,
+        );
+        for y in start..start + side {
+            for x in start..start + side {
+                let (lx, ly) = (x - start, y - start);
+                let (ox, oy) = ((side - w) / 2, (side - h) / 2);
+                let mut c = [options.light.0, options.light.1, options.light.2];
+                if lx >= ox && ly >= oy && lx < ox + w && ly < oy + h {
+                    let p = logo.pixel(
+                        (((lx - ox) as f64 / fit) as u32).min(logo.width - 1),
+                        (((ly - oy) as f64 / fit) as u32).min(logo.height - 1),
+                    );
+                    let a = u32::from(p[3]);
+                    for i in 0..3 {
+                        c[i] = ((u32::from(p[i]) * a + u32::from(c[i]) * (255 - a)) / 255) as u8;
+                    }
+                }
+                let i = ((y * px + x) * 3) as usize;
+                pixels[i..i + 3].copy_from_slice(&c);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut out, px, px);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+    }
+    out
+}
+
+fn render_svg(code: &QrCode, options: &QrOptions, logo: Option<&QrLogo>) -> String {
+    let modules = code.width() as u32;
+    let total = modules + 2 * options.margin;
+    let px = (options.size / total).max(1) * total;
+
+    let mut path = String::new();
+    for y in 0..modules {
+        for x in 0..modules {
+            if is_dark(code, modules, x as i64, y as i64) {
+                write!(
+                    path,
+                    "M{},{}h1v1h-1z",
+                    x + options.margin,
+                    y + options.margin
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let mut svg = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{px}" height="{px}" viewBox="0 0 {total} {total}" shape-rendering="crispEdges">
+<rect width="{total}" height="{total}" fill="{light}"/>
+<path d="{path}" fill="{dark}"/>
+"#,
+        px = px,
+        total = total,
+        light = options.light.hex(),
+        dark = options.dark.hex(),
+        path = path,
+    );
+    if let Some(logo) = logo {
+        let side = logo_side(modules);
+        let start = options.margin + (modules - side) / 2;
+        write!(
+            svg,
+            r#"<rect x="{start}" y="{start}" width="{side}" height="{side}" fill="{light}"/>
+<image x="{start}" y="{start}" width="{side}" height="{side}" href="data:image/png;base64,{data}"/>
+"#,
+            start = start,
+            side = side,
+            light = options.light.hex(),
+            data = base64::encode(&logo.png),
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(request: QrRequest) -> QrOptions {
+        QrOptions::from_request(&request).unwrap()
+    }
+
+    fn red_logo() -> QrLogo {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 2, 2);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[255, 0, 0, 255].repeat(4))
+                .unwrap();
+        }
+        QrLogo::from_png(png).unwrap()
+    }
+
+    fn decode_png(data: &[u8]) -> (u32, Vec<u8>) {
+        let decoder = png::Decoder::new(Cursor::new(data));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!(info.width, info.height);
+        (info.width, buf)
+    }
+
+    #[test]
+    fn png() {
+        let o = options(QrRequest {
+            size: Some(250),
+            margin: Some(2),
+            dark: Some("#102030".to_owned()),
+            ..Default::default()
+        });
+        let data = render("https://utrakr.app/abcdefgh", &o, None).unwrap();
+        let (width, pixels) = decode_png(&data);
+        // 29 modules for version 3 plus the margin, at 7px each
+        assert_eq!(width, 33 * 7);
+        // the quiet zone is light, the finder pattern's corner is dark
+        assert_eq!(&pixels[0..3], &[255, 255, 255]);
+        let corner = ((2 * 7 * width + 2 * 7) * 3) as usize;
+        assert_eq!(&pixels[corner..corner + 3], &[0x10, 0x20, 0x30]);
+    }
+
+    #[test]
+    fn png_logo() {
+        let o = options(QrRequest {
+            size: Some(290),
+            margin: Some(2),
+            logo: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(o.ec, EcLevel::H);
+        let data = render("https://utrakr.app/abcdefgh", &o, Some(&red_logo())).unwrap();
+        let (width, pixels) = decode_png(&data);
+        let center = ((width / 2 * width + width / 2) * 3) as usize;
+        assert_eq!(&pixels[center..center + 3], &[255, 0, 0]);
+    }
+
+    #[test]
+    fn svg() {
+        let o = options(QrRequest {
+            format: Some(QrFormat::Svg),
+            ec: Some("L".to_owned()),
+            light: Some("fafafa".to_owned()),
+            ..Default::default()
+        });
+        let data = render("https://utrakr.app/abcdefgh", &o, Some(&red_logo())).unwrap();
+        let svg = String::from_utf8(data).unwrap();
+        assert!(svg.contains("<svg"));
+        assert!(svg.contains(r##"fill="#fafafa""##));
+        assert!(svg.contains("data:image/png;base64,"));
+        assert_eq!(o.content_type(), "image/svg+xml");
+    }
+
+    #[test]
+    fn invalid() {
+        let invalid = |request: QrRequest| QrOptions::from_request(&request).is_err();
+        let color = |c: &str| Some(c.to_owned());
+        assert!(invalid(QrRequest {
+            ec: Some("X".to_owned()),
+            ..Default::default()
+        }));
+        assert!(invalid(QrRequest {
+            dark: color("zzzzzz"),
+            ..Default::default()
+        }));
+        assert!(invalid(QrRequest {
+            light: color("fff"),
+            ..Default::default()
+        }));
+        assert!(invalid(QrRequest {
+            size: Some(0),
+            ..Default::default()
+        }));
+        assert!(invalid(QrRequest {
+            size: Some(100_000),
+            ..Default::default()
+        }));
+        assert!(invalid(QrRequest {
+            margin: Some(100),
+            ..Default::default()
+        }));
+        assert!(!invalid(QrRequest::default()));
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use fehler::*;
+use tide::utils::async_trait;
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+use crate::utils::client_ip;
+
+/// In memory buckets are pruned once there are this many.
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+/// A token bucket that holds up to `burst` requests and refills `per_minute` of them a minute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Limit {
+    fn is_off(&self) -> bool {
+        self.burst == 0 || self.per_minute == 0
+    }
+
+    fn tokens_per_ms(&self) -> f64 {
+        self.per_minute as f64 / 60_000.0
+    }
+}
+
+/// `<burst>:<per minute>`, ie `20:10`, `0:0` turns the limit off.
+impl FromStr for Limit {
+    type Err = anyhow::Error;
+
+    #[throws(anyhow::Error)]
+    fn from_str(s: &str) -> Limit {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(burst), Some(per_minute)) => Limit {
+                burst: burst.trim().parse()?,
+                per_minute: per_minute.trim().parse()?,
+            },
+            _ => throw!(anyhow::anyhow!(
+                "expected <burst>:<per minute>, got [{}]",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    tokens: f64,
+    updated_ms: i64,
+    /// when the bucket is back at its burst, with the limit it was last taken with
+    full_at_ms: i64,
+}
+
+impl Bucket {
+    fn full(limit: Limit, now_ms: i64) -> Bucket {
+        Bucket {
+            tokens: limit.burst as f64,
+            updated_ms: now_ms,
+            full_at_ms: now_ms,
+        }
+    }
+
+    fn refill(&mut self, limit: Limit, now_ms: i64) {
+        let elapsed = (now_ms - self.updated_ms).max(0) as f64;
+        self.tokens = (self.tokens + elapsed * limit.tokens_per_ms()).min(limit.burst as f64);
+        self.updated_ms = now_ms;
+    }
+
+    /// Takes a token, or says in how many ms there will be one.
+    fn take(&mut self, limit: Limit, now_ms: i64) -> Option<i64> {
+        self.refill(limit, now_ms);
+        let retry_ms = if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - self.tokens) / limit.tokens_per_ms()).ceil() as i64)
+        };
+        let missing = limit.burst as f64 - self.tokens;
+        self.full_at_ms = now_ms + (missing / limit.tokens_per_ms()).ceil() as i64;
+        retry_ms
+    }
+}
+
+/// Same as `Bucket::take`, kept in a redis hash so every instance shares the buckets.
+const REDIS_TAKE: &str = r"
+local burst = tonumber(ARGV[1])
+local per_ms = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local stored = redis.call('HMGET', KEYS[1], 'tokens', 'ms')
+local tokens = tonumber(stored[1]) or burst
+local updated = tonumber(stored[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - updated) * per_ms)
+local retry = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  retry = math.ceil((1 - tokens) / per_ms)
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'ms', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(burst / per_ms) + 1000)
+return retry
+";
+
+/// Where the buckets live, `Memory` is per process.
+#[derive(Clone)]
+pub enum RateLimitStore {
+    Memory(Arc<Mutex<HashMap<String, Bucket>>>),
+    Redis(redis::Client),
+}
+
+impl RateLimitStore {
+    pub fn memory() -> RateLimitStore {
+        RateLimitStore::Memory(Arc::new(Mutex::new(HashMap::new())))
+    }
+
+    #[throws(anyhow::Error)]
+    pub fn redis(redis_conn: &str) -> RateLimitStore {
+        RateLimitStore::Redis(redis::Client::open(redis_conn)?)
+    }
+
+    /// Takes a token from the bucket at `key`, or says in how many ms there will be one.
+    #[throws(anyhow::Error)]
+    async fn take(&self, key: &str, limit: Limit, now_ms: i64) -> Option<i64> {
+        match self {
+            RateLimitStore::Memory(buckets) => {
+                let mut buckets = buckets.lock().unwrap();
+                if buckets.len() >= MAX_MEMORY_BUCKETS {
+                    // every route has its own limit, so each bucket knows when it is full
+                    buckets.retain(|_, b| b.full_at_ms > now_ms);
+                }
+                buckets
+                    .entry(key.to_owned())
+                    .or_insert_with(|| Bucket::full(limit, now_ms))
+                    .take(limit, now_ms)
+            }
+            RateLimitStore::Redis(client) => {
+                let mut con = client.get_async_connection().await?;
+                let retry_ms: i64 = redis::Script::new(REDIS_TAKE)
+                    .key(key)
+                    .arg(limit.burst)
+                    .arg(limit.tokens_per_ms())
+                    .arg(now_ms)
+                    .invoke_async(&mut con)
+                    .await?;
+                Some(retry_ms).filter(|ms| *ms > 0)
+            }
+        }
+    }
+}
+
+/// Who a request is limited as, set as a request extension by whatever authenticated it.
+/// Requests without one are limited by ip.
+#[derive(Debug, Clone)]
+pub enum Client {
+    ApiKey(String),
+    Email(String),
+    Ip(String),
+}
+
+impl Client {
+    fn key(&self) -> String {
+        match self {
+            Client::ApiKey(k) => format!("key:{}", k),
+            Client::Email(e) => format!("email:{}", e),
+            Client::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+}
+
+/// Answers 429 with a `retry-after` once a client has used up the route's bucket.
+#[derive(Clone)]
+pub struct RateLimit {
+    route: &'static str,
+    limit: Limit,
+    store: RateLimitStore,
+    /// see `client_ip`
+    trusted_proxy_hops: usize,
+}
+
+impl RateLimit {
+    pub fn new(
+        route: &'static str,
+        limit: Limit,
+        store: RateLimitStore,
+        trusted_proxy_hops: usize,
+    ) -> RateLimit {
+        RateLimit {
+            route,
+            limit,
+            store,
+            trusted_proxy_hops,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Takes a token for the client of `req`, or says in how many ms there will be one.
+    async fn retry_ms<State>(&self, req: &Request<State>) -> Option<i64> {
+        if self.limit.is_off() {
+            return None;
+        }
+        let client = match req.ext::<Client>() {
+            Some(c) => c.key(),
+            None => Client::Ip(client_ip(req, self.trusted_proxy_hops).unwrap_or_default()).key(),
+        };
+        let key = format!("rate_limit:{}:{}", self.route, client);
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        match self.store.take(&key, self.limit, now_ms).await {
+            Ok(Some(ms)) => {
+                debug!("rate limited [{}]", key);
+                Some(ms)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                // better to serve without a limit than not at all
+                warn!("unable to check rate limit [{}], {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Takes a token for the client of `req`, for handlers that limit only part of what they do.
+    pub async fn allows<State>(&self, req: &Request<State>) -> bool {
+        self.retry_ms(req).await.is_none()
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RateLimit {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        match self.retry_ms(&req).await {
+            Some(ms) => Ok(Response::builder(StatusCode::TooManyRequests)
+                .header("retry-after", ((ms + 999) / 1000).to_string())
+                .build()),
+            None => Ok(next.run(req).await),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "20:10".parse::<Limit>().unwrap(),
+            Limit {
+                burst: 20,
+                per_minute: 10
+            }
+        );
+        assert!("0:0".parse::<Limit>().unwrap().is_off());
+        assert!("20".parse::<Limit>().is_err());
+        assert!("a:1".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn bucket() {
+        let limit = Limit {
+            burst: 2,
+            per_minute: 60,
+        };
+        let mut bucket = Bucket::full(limit, 0);
+        assert_eq!(bucket.take(limit, 0), None);
+        assert_eq!(bucket.take(limit, 0), None);
+        assert_eq!(bucket.take(limit, 0), Some(1000));
+        assert_eq!(bucket.take(limit, 400), Some(600));
+        assert_eq!(bucket.take(limit, 1000), None);
+
+        // never refills past the burst
+        assert_eq!(bucket.take(limit, 60_000), None);
+        assert_eq!(bucket.take(limit, 60_000), None);
+        assert!(bucket.take(limit, 60_000).is_some());
+    }
+
+    #[async_std::test]
+    async fn memory_store() {
+        let limit = Limit {
+            burst: 1,
+            per_minute: 1,
+        };
+        let store = RateLimitStore::memory();
+        assert_eq!(store.take("a", limit, 0).await.unwrap(), None);
+        assert_eq!(store.take("a", limit, 0).await.unwrap(), Some(60_000));
+        assert_eq!(store.take("b", limit, 0).await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn memory_store_prunes_with_each_buckets_limit() {
+        let slow = Limit {
+            burst: 1,
+            per_minute: 1,
+        };
+        let fast = Limit {
+            burst: 1,
+            per_minute: 60_000,
+        };
+        let store = RateLimitStore::memory();
+        assert_eq!(store.take("slow", slow, 0).await.unwrap(), None);
+        for i in 1..MAX_MEMORY_BUCKETS {
+            store.take(&i.to_string(), fast, 0).await.unwrap();
+        }
+        // pruning on a fast route must not refill the slow bucket as if it were fast
+        assert_eq!(store.take("fast", fast, 10).await.unwrap(), None);
+        if let RateLimitStore::Memory(buckets) = &store {
+            assert_eq!(buckets.lock().unwrap().len(), 2);
+        }
+        assert!(store.take("slow", slow, 10).await.unwrap().is_some());
+    }
+}
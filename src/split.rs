@@ -0,0 +1,79 @@
+use crate::dao::url_dao::Destination;
+
+/// Pick a variant for a visitor. The choice only depends on the visitor and the micro url id so
+/// that the same visitor keeps landing on the same destination.
+pub fn choose_variant(visitor: &str, id: &str, destinations: &[Destination]) -> Option<usize> {
+    let total: u64 = destinations.iter().map(|d| u64::from(d.weight)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = fnv1a(&[visitor.as_bytes(), b"/", id.as_bytes()]) % total;
+    for (i, destination) in destinations.iter().enumerate() {
+        let weight = u64::from(destination.weight);
+        if point < weight {
+            return Some(i);
+        }
+        point -= weight;
+    }
+    None
+}
+
+// stable across builds and platforms, unlike the std hasher
+fn fnv1a(parts: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        for b in part.iter() {
+            hash ^= u64::from(*b);
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destinations(weights: &[u32]) -> Vec<Destination> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, w)| Destination {
+                long_url: format!("http://example.com/{}", i),
+                weight: *w,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sticky() {
+        let d = destinations(&[1, 1, 1]);
+        let first = choose_variant("01EM4Y6A3J5QH2T8C3V1XK9F0B", "abcdefgh", &d);
+        for _ in 0..10 {
+            assert_eq!(
+                first,
+                choose_variant("01EM4Y6A3J5QH2T8C3V1XK9F0B", "abcdefgh", &d)
+            );
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        let d = destinations(&[3, 1, 0]);
+        let mut counts = [0; 3];
+        for i in 0..4000 {
+            let v = choose_variant(&format!("visitor{}", i), "abcdefgh", &d).unwrap();
+            counts[v] += 1;
+        }
+        assert_eq!(counts[2], 0);
+        assert!(counts[0] > 2 * counts[1], "{:?}", counts);
+        assert!(counts[1] > 0, "{:?}", counts);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(choose_variant("v", "id", &[]), None);
+        assert_eq!(choose_variant("v", "id", &destinations(&[0, 0])), None);
+    }
+}
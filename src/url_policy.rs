@@ -0,0 +1,230 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use fehler::*;
+
+use crate::utils::url_host;
+
+/// A domain, matching its subdomains too, or a url pattern where `*` matches anything.
+#[derive(Debug, Clone, PartialEq)]
+enum Pattern {
+    Domain(String),
+    Url(String),
+}
+
+impl Pattern {
+    fn parse(s: &str) -> Pattern {
+        let s = s.to_lowercase();
+        if s.contains('/') {
+            Pattern::Url(s)
+        } else {
+            Pattern::Domain(s.trim_start_matches("*.").to_owned())
+        }
+    }
+
+    fn matches(&self, url: &str, host: Option<&str>) -> bool {
+        match self {
+            Pattern::Domain(d) => host.is_some_and(|h| {
+                h == d || (h.ends_with(d.as_str()) && h[..h.len() - d.len()].ends_with('.'))
+            }),
+            Pattern::Url(p) => glob_matches(p, url),
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Pattern::Domain(s) | Pattern::Url(s) => s,
+        }
+    }
+}
+
+fn glob_matches(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match s.strip_prefix(first) {
+        Some(r) => r,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        if i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.is_empty()
+}
+
+/// Which destinations micro urls may point at. Anything denied is blocked, and when there is an
+/// allowlist anything not on it is blocked too.
+#[derive(Debug, Default, Clone)]
+pub struct UrlPolicy {
+    deny: Vec<Pattern>,
+    allow: Vec<Pattern>,
+}
+
+impl UrlPolicy {
+    /// One rule per line, `deny <pattern>` or `allow <pattern>`, `#` starts a comment.
+    #[throws(anyhow::Error)]
+    pub fn parse(s: &str) -> UrlPolicy {
+        let mut policy = UrlPolicy::default();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let rules = match words.next() {
+                Some("deny") => &mut policy.deny,
+                Some("allow") => &mut policy.allow,
+                _ => throw!(anyhow::anyhow!("line {}, expected allow or deny", n + 1)),
+            };
+            match (words.next(), words.next()) {
+                (Some(p), None) => rules.push(Pattern::parse(p)),
+                _ => throw!(anyhow::anyhow!("line {}, expected a single pattern", n + 1)),
+            }
+        }
+        policy
+    }
+
+    /// The rule that blocks `url`, none when it is fine.
+    pub fn blocked_by(&self, url: &str) -> Option<String> {
+        let url = url.to_lowercase();
+        let host = url_host(&url);
+        let host = host.as_deref().map(|h| h.trim_end_matches('.'));
+        if let Some(p) = self.deny.iter().find(|p| p.matches(&url, host)) {
+            return Some(format!("deny {}", p.as_str()));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| p.matches(&url, host)) {
+            return Some("not allowed".to_owned());
+        }
+        None
+    }
+}
+
+/// The `UrlPolicy` from a file, reloaded when the file changes.
+#[derive(Clone, Default)]
+pub struct UrlPolicyFile {
+    path: Option<PathBuf>,
+    policy: Arc<RwLock<UrlPolicy>>,
+    modified: Arc<RwLock<Option<SystemTime>>>,
+}
+
+impl UrlPolicyFile {
+    /// Allows everything when there is no path.
+    #[throws(anyhow::Error)]
+    pub fn load(path: Option<&Path>) -> UrlPolicyFile {
+        let file = UrlPolicyFile {
+            path: path.map(|p| p.to_owned()),
+            ..Default::default()
+        };
+        file.reload()?;
+        file
+    }
+
+    /// Reads the file again, keeping the current policy when it does not parse.
+    #[throws(anyhow::Error)]
+    pub fn reload(&self) {
+        if let Some(ref path) = self.path {
+            let modified = std::fs::metadata(path)?.modified().ok();
+            let policy = UrlPolicy::parse(&std::fs::read_to_string(path)?)?;
+            info!(
+                "loaded url policy from [{}], {} deny and {} allow rules",
+                path.display(),
+                policy.deny.len(),
+                policy.allow.len()
+            );
+            *self.policy.write().unwrap() = policy;
+            *self.modified.write().unwrap() = modified;
+        }
+    }
+
+    /// Reloads when the file was modified since it was last read.
+    pub fn reload_if_modified(&self) {
+        if let Some(ref path) = self.path {
+            let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            if modified != *self.modified.read().unwrap() {
+                if let Err(e) = self.reload() {
+                    warn!(
+                        "unable to reload url policy, keeping the current one, {}",
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn blocked_by(&self, url: &str) -> Option<String> {
+        self.policy.read().unwrap().blocked_by(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches(
+            "https://example.com/*",
+            "https://example.com/a/b"
+        ));
+        assert!(glob_matches("*/login*", "https://example.com/login?x=1"));
+        assert!(glob_matches(
+            "https://*.example.com/*.exe",
+            "https://a.example.com/x.exe"
+        ));
+        assert!(!glob_matches(
+            "https://*.example.com/*.exe",
+            "https://a.example.com/x.exe?"
+        ));
+        assert!(!glob_matches(
+            "https://example.com/",
+            "https://example.com/a"
+        ));
+    }
+
+    #[test]
+    fn deny() {
+        let policy = UrlPolicy::parse(
+            "# phishing\ndeny evil.com\ndeny https://docs.example.com/forms/*  # abused\n",
+        )
+        .unwrap();
+        assert_eq!(
+            policy.blocked_by("https://login.EVIL.com/x"),
+            Some("deny evil.com".to_owned())
+        );
+        assert_eq!(
+            policy.blocked_by("https://evil.com./"),
+            Some("deny evil.com".to_owned())
+        );
+        assert_eq!(policy.blocked_by("https://notevil.com/"), None);
+        assert!(policy
+            .blocked_by("https://docs.example.com/forms/123")
+            .is_some());
+        assert_eq!(policy.blocked_by("https://docs.example.com/other"), None);
+    }
+
+    #[test]
+    fn allow() {
+        let policy = UrlPolicy::parse("allow example.com\ndeny bad.example.com").unwrap();
+        assert_eq!(policy.blocked_by("https://www.example.com/"), None);
+        assert_eq!(
+            policy.blocked_by("https://other.com/"),
+            Some("not allowed".to_owned())
+        );
+        assert!(policy.blocked_by("https://bad.example.com/").is_some());
+        assert!(policy.blocked_by("not a url").is_some());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(UrlPolicy::parse("block evil.com").is_err());
+        assert!(UrlPolicy::parse("deny a.com b.com").is_err());
+        assert!(UrlPolicy::parse("deny").is_err());
+    }
+}
@@ -1,7 +1,225 @@
+use std::collections::HashSet;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::dao::api_key_dao::API_KEY_LAST_USED_KEY;
+use crate::dao::url_dao::LINKS_KEY;
+use crate::id_generator::ID_COUNTER_KEY;
+
 pub fn trim_trailing_slash(s: &str) -> String {
     s.trim_end_matches('/').into()
 }
 
+/// The host of a url, none when it does not parse or has no host.
+pub fn url_host(url: &str) -> Option<String> {
+    http_types::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_owned()))
+}
+
+/// The url as the url parser writes it, so that ie `HTTPS://Example.com` and `https://example.com/`
+/// are the same. Left as is, apart from surrounding whitespace, when it does not parse.
+pub fn normalize_url(url: &str) -> String {
+    match http_types::Url::parse(url.trim()) {
+        Ok(u) => u.to_string(),
+        Err(_) => url.trim().to_owned(),
+    }
+}
+
+/// The visitor's ip. Each of the `trusted_proxy_hops` proxies in front of us appends who it got
+/// the request from to `x-forwarded-for`, anything before their entries is made up by the client.
+pub fn client_ip<State>(req: &tide::Request<State>, trusted_proxy_hops: usize) -> Option<String> {
+    req.header("x-forwarded-for")
+        .and_then(|f| forwarded_ip(f.as_str(), trusted_proxy_hops))
+        .or_else(|| {
+            req.peer_addr()
+                .map(|a| a.rsplitn(2, ':').last().unwrap_or(a).to_owned())
+        })
+}
+
+/// The entry `trusted_proxy_hops` from the right of an `x-forwarded-for`, none when there are not
+/// that many.
+fn forwarded_ip(forwarded: &str, trusted_proxy_hops: usize) -> Option<String> {
+    if trusted_proxy_hops == 0 {
+        return None;
+    }
+    forwarded
+        .rsplit(',')
+        .nth(trusted_proxy_hops - 1)
+        .map(|ip| ip.trim())
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| ip.to_owned())
+}
+
+/// Escape text so that it can be placed in html content or a quoted attribute.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Encode a string as a javascript string literal that is safe to place inside a script tag.
+pub fn js_string(s: &str) -> String {
+    serde_json::to_string(s)
+        .expect("strings always serialize")
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+}
+
+/// Characters percent encoded in ids, the ascii ones are never part of an id anyway.
+const ID_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Ids that would be taken for something other than a micro url, routes and the redis keys that
+/// sit next to the micro urls.
+const RESERVED_IDS: &[&str] = &[
+    "api",
+    "private",
+    "favicon.ico",
+    "robots.txt",
+    LINKS_KEY,
+    ID_COUNTER_KEY,
+    API_KEY_LAST_USED_KEY,
+];
+
+/// Whether a micro url could be stored under `id`. Every other redis key has a `:` in it or is
+/// reserved, so looking those up as ids would hand out what they hold.
+pub fn is_micro_url_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(':') && !RESERVED_IDS.contains(&id.to_lowercase().as_str())
+}
+
+/// An id as it is stored, from a path segment that may be percent encoded. Unicode ids are NFC
+/// normalized so that the different ways of typing the same word find the same micro url.
+pub fn normalize_id(id: &str) -> String {
+    percent_decode_str(id).decode_utf8_lossy().nfc().collect()
+}
+
+/// An id as it goes in a uri, unicode ids are percent encoded.
+pub fn encode_id(id: &str) -> String {
+    utf8_percent_encode(id, ID_ENCODE_SET).to_string()
+}
+
+/// Invisible characters that make an alias look like another, see unicode's
+/// `Default_Ignorable_Code_Point`, bidi controls included.
+fn is_default_ignorable(c: char) -> bool {
+    matches!(c,
+        '\u{ad}' | '\u{34f}' | '\u{61c}' | '\u{115f}'..='\u{1160}' | '\u{17b4}'..='\u{17b5}'
+        | '\u{180b}'..='\u{180f}' | '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}'
+        | '\u{2060}'..='\u{206f}' | '\u{3164}' | '\u{fe00}'..='\u{fe0f}' | '\u{feff}'
+        | '\u{ffa0}' | '\u{fff0}'..='\u{fff8}' | '\u{1bca0}'..='\u{1bca3}'
+        | '\u{1d173}'..='\u{1d17a}' | '\u{e0000}'..='\u{e0fff}')
+}
+
+/// Roughly the emoji, symbols drawn as pictures.
+fn is_pictographic(c: char) -> bool {
+    matches!(c, '\u{203c}'..='\u{3299}' | '\u{1f000}'..='\u{1faff}') && !c.is_alphanumeric()
+}
+
+/// Whether a normalized alias can be an id, letters and digits of any script, emoji, `-` and `_`.
+pub fn is_valid_alias(alias: &str) -> bool {
+    let chars: Vec<char> = alias.chars().collect();
+    let emoji_before =
+        |i: usize| i > 0 && (is_pictographic(chars[i - 1]) || chars[i - 1] == '\u{fe0f}');
+    let emoji_after = |i: usize| chars.get(i + 1).is_some_and(|c| is_pictographic(*c));
+    (1..=64).contains(&chars.len())
+        && !RESERVED_IDS.contains(&alias.to_lowercase().as_str())
+        && trim_id(alias) == alias
+        && !alias.ends_with('+')
+        && chars.iter().enumerate().all(|(i, &c)| match c {
+            _ if c.is_ascii() => c.is_ascii_alphanumeric() || c == '-' || c == '_',
+            // joins emoji into one, like a family, and asks for an emoji to be drawn in color
+            '\u{200d}' => emoji_before(i) && emoji_after(i),
+            '\u{fe0f}' => i > 0 && is_pictographic(chars[i - 1]),
+            _ => !c.is_whitespace() && !c.is_control() && !is_default_ignorable(c),
+        })
+}
+
+/// What people copy along with an id, ie the `).` of `(see utrakr.app/abc123).`
+const TRAILING_PUNCTUATION: &str = ".,;:!?)]}>'\"`*";
+
+/// `id` without trailing punctuation and whitespace, also when it is percent encoded.
+pub fn trim_id(id: &str) -> &str {
+    let mut id = id;
+    loop {
+        let escaped = id
+            .len()
+            .checked_sub(3)
+            .filter(|i| id.is_char_boundary(*i) && id[*i..].starts_with('%'))
+            .and_then(|i| u8::from_str_radix(&id[i + 1..], 16).ok().map(|b| (i, b)));
+        id = match (id.chars().last(), escaped) {
+            (Some(c), _) if c.is_whitespace() || TRAILING_PUNCTUATION.contains(c) => {
+                &id[..id.len() - c.len_utf8()]
+            }
+            (_, Some((i, b)))
+                if (b as char).is_ascii_whitespace()
+                    || TRAILING_PUNCTUATION.contains(b as char) =>
+            {
+                &id[..i]
+            }
+            _ => return id,
+        };
+    }
+}
+
+/// Characters easy to type or read as one another.
+const CONFUSABLE: &[&str] = &["0Oo", "1lI"];
+
+/// The ids one typo away from `id`: another case of it or of one of its characters, a character
+/// left out, two next to each other swapped, or one read as another. Few enough to look up
+/// on every miss.
+pub fn typo_candidates(id: &str) -> Vec<String> {
+    let chars: Vec<char> = id.chars().collect();
+    let with = |i: usize, replacement: &[char]| -> String {
+        chars[..i]
+            .iter()
+            .chain(replacement)
+            .chain(&chars[i + 1..])
+            .collect()
+    };
+    let mut candidates = vec![id.to_lowercase(), id.to_uppercase()];
+    for (i, c) in chars.iter().enumerate() {
+        let other_case: Vec<char> = if c.is_lowercase() {
+            c.to_uppercase().collect()
+        } else {
+            c.to_lowercase().collect()
+        };
+        candidates.push(with(i, &other_case));
+        candidates.push(with(i, &[]));
+        if i + 1 < chars.len() {
+            let mut swapped = chars.clone();
+            swapped.swap(i, i + 1);
+            candidates.push(swapped.into_iter().collect());
+        }
+        for group in CONFUSABLE.iter().filter(|g| g.contains(*c)) {
+            candidates.extend(group.chars().filter(|o| o != c).map(|o| with(i, &[o])));
+        }
+    }
+    let mut seen = HashSet::new();
+    candidates.retain(|c| !c.is_empty() && c != id && seen.insert(c.to_owned()));
+    candidates
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -18,6 +236,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_url(" HTTPS://Example.COM:443"),
+            "https://example.com/"
+        );
+        assert_eq!(
+            normalize_url("https://example.com/a/../b?q=1#top"),
+            "https://example.com/b?q=1#top"
+        );
+        assert_ne!(
+            normalize_url("https://example.com/A"),
+            normalize_url("https://example.com/a")
+        );
+        assert_eq!(normalize_url("not a url "), "not a url");
+    }
+
     #[test]
     fn multiple() {
         assert_eq!(
@@ -38,4 +273,126 @@ mod tests {
     fn empty() {
         assert_eq!(trim_trailing_slash(""), "");
     }
+
+    #[test]
+    fn host() {
+        assert_eq!(
+            url_host("https://www.example.com:8080/a?b=c").as_deref(),
+            Some("www.example.com")
+        );
+        assert_eq!(url_host("mailto:someone@example.com"), None);
+        assert_eq!(url_host("not a url"), None);
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_html("http://example.com/?a=1&b=\"<script>'"),
+            "http://example.com/?a=1&amp;b=&quot;&lt;script&gt;&#x27;"
+        );
+        assert_eq!(escape_html("plain"), "plain");
+    }
+
+    #[test]
+    fn js() {
+        assert_eq!(js_string("a\"b"), "\"a\\\"b\"");
+        assert_eq!(
+            js_string("</script><script>"),
+            "\"\\u003c/script\\u003e\\u003cscript\\u003e\""
+        );
+    }
+
+    #[test]
+    fn trims_ids() {
+        assert_eq!(trim_id("abc123)."), "abc123");
+        assert_eq!(trim_id("abc123 \t"), "abc123");
+        assert_eq!(trim_id("abc123%20"), "abc123");
+        assert_eq!(trim_id("abc123%29%2E"), "abc123");
+        assert_eq!(trim_id("abc123%2"), "abc123%2");
+        // part of ids
+        assert_eq!(trim_id("a-b_c+"), "a-b_c+");
+        assert_eq!(trim_id("abc+)."), "abc+");
+        assert_eq!(trim_id("é!"), "é");
+        assert_eq!(trim_id("..."), "");
+    }
+
+    #[test]
+    fn forwarded() {
+        let forwarded = "6.6.6.6, 1.2.3.4, 10.0.0.1";
+        assert_eq!(forwarded_ip(forwarded, 0), None);
+        assert_eq!(forwarded_ip(forwarded, 1).as_deref(), Some("10.0.0.1"));
+        assert_eq!(forwarded_ip(forwarded, 2).as_deref(), Some("1.2.3.4"));
+        assert_eq!(forwarded_ip(forwarded, 4), None);
+        assert_eq!(forwarded_ip("1.2.3.4", 1).as_deref(), Some("1.2.3.4"));
+        assert_eq!(forwarded_ip("1.2.3.4, ", 1), None);
+    }
+
+    #[test]
+    fn typos() {
+        let candidates = typo_candidates("aB1");
+        for typo in &["ab1", "AB1", "B1", "a1", "aB", "Ba1", "a1B", "aBl", "aBI"] {
+            assert!(candidates.contains(&typo.to_string()), "{}", typo);
+        }
+        assert!(!candidates.contains(&"aB1".to_owned()));
+        assert!(!candidates.contains(&"Ab1".to_owned()));
+        assert!(!candidates.contains(&"aBc".to_owned()));
+        assert!(!candidates.contains(&"aB12".to_owned()));
+        assert_eq!(typo_candidates("a"), vec!["A"]);
+        assert!(typo_candidates("").is_empty());
+        // few enough for a lookup per miss
+        assert!(typo_candidates("pm1otTq7").len() < 40);
+    }
+
+    #[test]
+    fn micro_url_ids() {
+        assert!(is_micro_url_id("abc123"));
+        assert!(is_micro_url_id("café"));
+        assert!(!is_micro_url_id(""));
+        assert!(!is_micro_url_id("health:abc123"));
+        assert!(!is_micro_url_id("Links"));
+        assert!(!is_micro_url_id("api_key_last_used"));
+    }
+
+    #[test]
+    fn unicode_ids() {
+        // e and a combining acute accent, as some keyboards type it
+        assert_eq!(normalize_id("cafe\u{301}"), "caf\u{e9}");
+        assert_eq!(normalize_id("cafe%CC%81"), "caf\u{e9}");
+        assert_eq!(normalize_id("%F0%9F%8E%89"), "🎉");
+        assert_eq!(normalize_id("abcDEF12"), "abcDEF12");
+        assert_eq!(encode_id("café"), "caf%C3%A9");
+        assert_eq!(encode_id("🎉"), "%F0%9F%8E%89");
+        assert_eq!(encode_id("a-b_C9"), "a-b_C9");
+        assert_eq!(normalize_id(&encode_id("привет")), "привет");
+
+        assert!(is_valid_alias("café"));
+        assert!(is_valid_alias("привет-мир"));
+        assert!(is_valid_alias("🎉🎉"));
+        assert!(is_valid_alias("sale_2020"));
+        assert!(!is_valid_alias(""));
+        assert!(!is_valid_alias("a b"));
+        assert!(!is_valid_alias("a/b"));
+        assert!(!is_valid_alias("sale!"));
+        assert!(!is_valid_alias("sale+"));
+        assert!(!is_valid_alias("API"));
+        assert!(!is_valid_alias("abc\u{202e}def"));
+        assert!(!is_valid_alias("links"));
+        assert!(!is_valid_alias("id_counter"));
+        assert!(!is_valid_alias("api_key_last_used"));
+
+        // invisible characters
+        for c in &[
+            '\u{200b}', '\u{200d}', '\u{2060}', '\u{feff}', '\u{ad}', '\u{fe0f}',
+        ] {
+            assert!(!is_valid_alias(&format!("sale{}", c)), "{:?}", c);
+            assert!(!is_valid_alias(&format!("sa{}le", c)), "{:?}", c);
+        }
+        // but not the ones inside emoji sequences
+        assert!(is_valid_alias("👨\u{200d}👩\u{200d}👧"));
+        assert!(is_valid_alias("❤\u{fe0f}"));
+        assert!(is_valid_alias("🏳\u{fe0f}\u{200d}🌈"));
+        assert!(!is_valid_alias("👨\u{200d}"));
+        assert!(!is_valid_alias("\u{200d}👨"));
+        assert!(!is_valid_alias(&"a".repeat(65)));
+    }
 }